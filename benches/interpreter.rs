@@ -0,0 +1,111 @@
+//! Instructions per second of the interpreter, on a tight loop and on ROMs
+//!
+//! ```sh
+//! cargo bench --bench interpreter
+//! cargo bench --bench interpreter --features jit
+//! ```
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+use chip_8::machine::instruction::Instruction;
+use chip_8::machine::{Address, Machine, Memory, Platform};
+
+/// Instructions run per iteration
+const STEPS: u64 = 10_000;
+
+/// Arithmetic and I updates, jumping back forever
+const LOOP: [u8; 14] = [
+    0x60, 0x00, // v0 := 0
+    0x70, 0x01, // v0 += 1
+    0x81, 0x04, // v1 += v0
+    0xa3, 0x00, // i := 0x300
+    0xf1, 0x1e, // i += v1
+    0x3f, 0xff, // skip_if vf == 255, never
+    0x12, 0x02, // jump 0x202
+];
+
+const ROMS: [(&str, &[u8]); 3] = [
+    ("loop", &LOOP),
+    ("corax+", include_bytes!("../programs/3-corax+.ch8")),
+    ("flags", include_bytes!("../programs/4-flags.ch8")),
+];
+
+fn machine(program: &[u8]) -> Machine {
+    let mut machine = Machine::new();
+    machine.load_program(program).unwrap();
+    machine
+}
+
+fn run(c: &mut Criterion) {
+    let mut group = c.benchmark_group("run");
+    group.throughput(Throughput::Elements(STEPS));
+    for (name, program) in ROMS {
+        group.bench_function(name, |b| {
+            b.iter_batched_ref(
+                || machine(program),
+                |machine| {
+                    for _ in 0..STEPS {
+                        machine.step().unwrap();
+                    }
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// Decoding the instructions of the loop, from memory every time or from the cache
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    let addresses = (0..LOOP.len() as Address)
+        .step_by(2)
+        .map(|offset| Memory::PROGRAM_ENTRYPOINT + offset);
+    group.throughput(Throughput::Elements(addresses.len() as u64));
+    let mut machine = machine(&LOOP);
+    group.bench_function("uncached", |b| {
+        b.iter(|| {
+            for addr in addresses.clone() {
+                let nibbles = machine.memory.nibbles_at(addr).unwrap();
+                criterion::black_box(Instruction::decode(nibbles, Platform::Chip8));
+            }
+        })
+    });
+    group.bench_function("cached", |b| {
+        b.iter(|| {
+            for addr in addresses.clone() {
+                criterion::black_box(machine.memory.instruction_at(addr).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+/// Same ROMs as [run], as compiled blocks
+#[cfg(feature = "jit")]
+fn jit(c: &mut Criterion) {
+    use chip_8::machine::Jit;
+
+    let mut group = c.benchmark_group("jit");
+    group.throughput(Throughput::Elements(STEPS));
+    for (name, program) in ROMS {
+        group.bench_function(name, |b| {
+            b.iter_batched_ref(
+                || {
+                    let mut machine = machine(program);
+                    machine.speed = STEPS as u32;
+                    (machine, Jit::new())
+                },
+                |(machine, jit)| jit.run(machine).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+#[cfg(not(feature = "jit"))]
+criterion_group!(benches, run, decode);
+#[cfg(feature = "jit")]
+criterion_group!(benches, run, decode, jit);
+criterion_main!(benches);
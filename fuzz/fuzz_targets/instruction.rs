@@ -0,0 +1,28 @@
+//! Every opcode decodes, on every platform, to an instruction that encodes
+//! back to it, and shows as text and as an opcode pattern matching it
+
+#![no_main]
+
+use chip_8::machine::instruction::Instruction;
+use chip_8::machine::Platform;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|opcode: u16| {
+    let [a, b] = opcode.to_be_bytes();
+    for platform in Platform::ALL {
+        let Some(instruction) = Instruction::decode([a >> 4, a & 0xf, b >> 4, b & 0xf], platform) else {
+            continue;
+        };
+        assert_eq!(instruction.encode(), opcode, "{platform}: {instruction:?}");
+        assert!(!instruction.to_string().is_empty(), "{platform}: {instruction:?}");
+
+        // Hex digits of the pattern are fixed, X, Y and N are operands
+        let pattern = instruction.pattern();
+        let digits = format!("{opcode:04X}");
+        for (digit, expected) in digits.chars().zip(pattern.chars()) {
+            if expected.is_ascii_hexdigit() {
+                assert_eq!(digit, expected, "{platform}: {opcode:04X} is not {pattern}");
+            }
+        }
+    }
+});
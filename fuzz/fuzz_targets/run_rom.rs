@@ -0,0 +1,38 @@
+//! Random bytes loaded as a program and run for a while, which must fail with
+//! an error rather than panic
+//!
+//! The first byte picks the quirks and the key held down, the rest is the program
+
+#![no_main]
+
+use chip_8::machine::{Machine, Quirks};
+use libfuzzer_sys::fuzz_target;
+
+/// Instructions run per input
+const STEPS: usize = 10_000;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&settings, program)) = data.split_first() else {
+        return;
+    };
+    let mut machine = Machine::new();
+    machine.seed(0);
+    machine.quirks = Quirks {
+        shift: settings & 1 != 0,
+        memory_increment: settings & 2 != 0,
+        jump: settings & 4 != 0,
+        vf_reset: settings & 8 != 0,
+        wrap: settings & 16 != 0,
+    };
+    if machine.load_program(program).is_err() {
+        return;
+    }
+    if settings & 32 != 0 {
+        machine.keypad.press(settings >> 6);
+    }
+    for _ in 0..STEPS {
+        if machine.step().is_err() {
+            break;
+        }
+    }
+});
@@ -0,0 +1,140 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rodio::{OutputStream, Sink, Source};
+
+use super::{same_sample, Audio, AudioSettings, Pattern, Synth};
+use crate::machine::Sample;
+
+/// Plays the sound on the default audio device with rodio
+pub struct DeviceAudio {
+    // _stream must live as long as the sink
+    _stream: OutputStream,
+    _sink: Sink,
+    playing: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    muted: Arc<AtomicBool>,
+    patterns: Sender<Option<Pattern>>,
+    pattern: Option<Pattern>,
+    samples: Sender<Option<Sample>>,
+    sample: Option<Sample>,
+    settings: Sender<AudioSettings>,
+}
+
+impl DeviceAudio {
+    pub fn new(settings: AudioSettings) -> Result<Self, Box<dyn core::error::Error>> {
+        let (_stream, stream_handle) = OutputStream::try_default()?;
+        let sink = Sink::try_new(&stream_handle)?;
+
+        let playing = Arc::new(AtomicBool::new(false));
+        let paused = Arc::new(AtomicBool::new(false));
+        let muted = Arc::new(AtomicBool::new(settings.muted));
+        let (patterns, pattern_receiver) = mpsc::channel();
+        let (samples, sample_receiver) = mpsc::channel();
+        let (settings_sender, settings_receiver) = mpsc::channel();
+        // The synth plays continuously, silent while not playing
+        sink.append(SynthSource {
+            synth: Synth::new(settings),
+            playing: playing.clone(),
+            paused: paused.clone(),
+            muted: muted.clone(),
+            patterns: pattern_receiver,
+            samples: sample_receiver,
+            settings: settings_receiver,
+        });
+
+        Ok(Self {
+            _stream,
+            _sink: sink,
+            playing,
+            paused,
+            muted,
+            patterns,
+            pattern: None,
+            samples,
+            sample: None,
+            settings: settings_sender,
+        })
+    }
+}
+
+impl Audio for DeviceAudio {
+    fn frame(&mut self, playing: bool, pattern: Option<Pattern>, sample: Option<&Sample>) {
+        if pattern != self.pattern {
+            self.pattern = pattern;
+            // The synth only goes away with the sink
+            let _ = self.patterns.send(pattern);
+        }
+        if !same_sample(sample, self.sample.as_ref()) {
+            self.sample = sample.cloned();
+            let _ = self.samples.send(self.sample.clone());
+        }
+        self.playing.store(playing, Ordering::Relaxed);
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    fn pause(&mut self) {
+        self.playing.store(false, Ordering::Relaxed);
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    fn toggle_mute(&mut self) {
+        let muted = !self.muted.fetch_xor(true, Ordering::Relaxed);
+        log::info!("audio muted: {muted}");
+    }
+
+    fn configure(&mut self, settings: AudioSettings) {
+        let _ = self.settings.send(settings);
+    }
+}
+
+/// [Synth] fed from the UI thread through atomics and channels
+struct SynthSource {
+    synth: Synth,
+    playing: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    muted: Arc<AtomicBool>,
+    patterns: Receiver<Option<Pattern>>,
+    samples: Receiver<Option<Sample>>,
+    settings: Receiver<AudioSettings>,
+}
+
+impl Iterator for SynthSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if let Some(pattern) = self.patterns.try_iter().last() {
+            self.synth.pattern = pattern;
+        }
+        if let Some(sample) = self.samples.try_iter().last() {
+            self.synth.play_sample(sample.as_ref());
+        }
+        if let Some(settings) = self.settings.try_iter().last() {
+            self.synth.configure(settings);
+        }
+        self.synth.playing = self.playing.load(Ordering::Relaxed);
+        self.synth.paused = self.paused.load(Ordering::Relaxed);
+        self.synth.muted = self.muted.load(Ordering::Relaxed);
+        Some(self.synth.next_sample())
+    }
+}
+
+impl Source for SynthSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        Synth::SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
@@ -0,0 +1,350 @@
+mod device;
+mod null;
+mod wav;
+
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::machine::{AudioPattern, Machine, Sample};
+
+pub use device::DeviceAudio;
+pub use null::NullAudio;
+pub use wav::WavAudio;
+
+/// Sound output, driven once per frame
+pub trait Audio {
+    /// Called 60 times per second, `playing` while the sound timer is non-zero
+    ///
+    /// The MegaChip `sample` plays on top of the tone, from its start when it
+    /// changes, see [Sample::same_start]
+    fn frame(&mut self, playing: bool, pattern: Option<Pattern>, sample: Option<&Sample>);
+
+    /// Stop the sound while the machine is paused, the next frame resumes the
+    /// sample where it stopped
+    fn pause(&mut self);
+
+    fn toggle_mute(&mut self);
+
+    /// Switch to the settings of another ROM, keeping the mute state
+    fn configure(&mut self, settings: AudioSettings);
+}
+
+/// Write the sound to `wav` if given, else play it on the default device
+///
+/// Falls back to [NullAudio] when there is no usable audio device
+pub fn open(settings: AudioSettings, wav: Option<&Path>) -> Result<Box<dyn Audio>, hound::Error> {
+    if let Some(path) = wav {
+        return Ok(Box::new(WavAudio::create(path, settings)?));
+    }
+    match DeviceAudio::new(settings) {
+        Ok(audio) => Ok(Box::new(audio)),
+        Err(error) => {
+            log::warn!("no audio device, sound disabled: {error}");
+            Ok(Box::new(NullAudio))
+        }
+    }
+}
+
+#[derive(Deserialize, clap::ValueEnum, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Waveform {
+    Square,
+    #[default]
+    Sine,
+    Triangle,
+}
+
+impl Waveform {
+    /// Sample in [-1, 1] at `phase` in [0, 1)
+    fn sample(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square if phase < 0.5 => 1.0,
+            Waveform::Square => -1.0,
+            Waveform::Sine => (phase * std::f32::consts::TAU).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+        }
+    }
+}
+
+/// `[audio]` section of the configuration
+///
+/// ```toml
+/// [audio]
+/// frequency = 440.0
+/// waveform = "square"
+/// volume = 0.2
+/// muted = false
+/// attack = 5 # ms
+/// release = 5 # ms
+/// ```
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct AudioConfig {
+    #[serde(default, deserialize_with = "deserialize_frequency")]
+    pub frequency: Option<f32>,
+    pub waveform: Option<Waveform>,
+    #[serde(default, deserialize_with = "deserialize_volume")]
+    pub volume: Option<f32>,
+    pub muted: Option<bool>,
+    pub attack: Option<u64>,
+    pub release: Option<u64>,
+}
+
+/// Check a tone frequency in Hz, which must be positive and finite for the
+/// phase of the waveforms to stay in [0, 1)
+pub fn parse_frequency(s: &str) -> Result<f32, String> {
+    let frequency: f32 = s.parse().map_err(|_| format!("invalid frequency {s:?}"))?;
+    check_frequency(frequency)
+}
+
+fn check_frequency(frequency: f32) -> Result<f32, String> {
+    match frequency.is_finite() && frequency > 0.0 {
+        true => Ok(frequency),
+        false => Err(format!("frequency must be a positive number of Hz, got {frequency}")),
+    }
+}
+
+fn deserialize_frequency<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f32>, D::Error> {
+    let frequency = f32::deserialize(deserializer)?;
+    check_frequency(frequency).map(Some).map_err(serde::de::Error::custom)
+}
+
+/// Check a volume, which must be in [0, 1]
+pub fn parse_volume(s: &str) -> Result<f32, String> {
+    let volume: f32 = s.parse().map_err(|_| format!("invalid volume {s:?}"))?;
+    check_volume(volume)
+}
+
+fn check_volume(volume: f32) -> Result<f32, String> {
+    match (0.0..=1.0).contains(&volume) {
+        true => Ok(volume),
+        false => Err(format!("volume must be between 0 and 1, got {volume}")),
+    }
+}
+
+fn deserialize_volume<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f32>, D::Error> {
+    let volume = f32::deserialize(deserializer)?;
+    check_volume(volume).map(Some).map_err(serde::de::Error::custom)
+}
+
+#[derive(Debug, Clone)]
+pub struct AudioSettings {
+    pub frequency: f32,
+    pub waveform: Waveform,
+    /// Amplitude in [0, 1]
+    pub volume: f32,
+    pub muted: bool,
+    /// Fade in when the sound timer starts, avoids a click
+    pub attack: Duration,
+    /// Fade out when the sound timer stops, avoids a click
+    pub release: Duration,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            frequency: 440.0,
+            waveform: Waveform::default(),
+            volume: 0.20,
+            muted: false,
+            attack: Duration::from_millis(5),
+            release: Duration::from_millis(5),
+        }
+    }
+}
+
+impl AudioSettings {
+    /// Apply a configuration on top of the current settings
+    pub fn apply(&mut self, config: &AudioConfig) {
+        if let Some(frequency) = config.frequency {
+            self.frequency = frequency;
+        }
+        if let Some(waveform) = config.waveform {
+            self.waveform = waveform;
+        }
+        if let Some(volume) = config.volume {
+            self.volume = volume;
+        }
+        if let Some(muted) = config.muted {
+            self.muted = muted;
+        }
+        if let Some(attack) = config.attack {
+            self.attack = Duration::from_millis(attack);
+        }
+        if let Some(release) = config.release {
+            self.release = Duration::from_millis(release);
+        }
+    }
+}
+
+/// XO-CHIP audio pattern and the rate its bits are played at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pattern {
+    pub bits: AudioPattern,
+    /// In bits per second
+    pub rate: f32,
+}
+
+impl Pattern {
+    const LEN: f32 = (std::mem::size_of::<AudioPattern>() * u8::BITS as usize) as f32;
+
+    pub fn of(machine: &Machine) -> Option<Self> {
+        Some(Self {
+            bits: machine.audio_pattern?,
+            rate: machine.audio_pattern_rate(),
+        })
+    }
+
+    /// Sample in [-1, 1] at `position` in [0, 1)
+    fn sample(&self, position: f32) -> f32 {
+        let bit = (position * Self::LEN) as usize;
+        match (self.bits[bit / 8] >> (7 - bit % 8)) & 1 {
+            1 => 1.0,
+            _ => -1.0,
+        }
+    }
+}
+
+/// Whether both are the same MegaChip sample playing, or both are none
+fn same_sample(a: Option<&Sample>, b: Option<&Sample>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.same_start(b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Endless sound, silent while not playing
+///
+/// Plays the configured tone, or the XO-CHIP audio pattern when there is one.
+/// The gain ramps linearly towards the playing state instead of jumping,
+/// following the attack and release durations. MegaChip samples are mixed
+/// in at their own rate, regardless of the sound timer
+pub struct Synth {
+    settings: AudioSettings,
+    pub playing: bool,
+    pub muted: bool,
+    /// Holds the MegaChip sample where it is until the next frame
+    pub paused: bool,
+    pub pattern: Option<Pattern>,
+    phase: f32,
+    gain: f32,
+    sample: Option<Sample>,
+    /// Index in `sample`, none once played when not looping
+    sample_position: Option<f32>,
+}
+
+impl Synth {
+    pub const SAMPLE_RATE: u32 = 44100;
+
+    pub fn new(settings: AudioSettings) -> Self {
+        Self {
+            muted: settings.muted,
+            settings,
+            playing: false,
+            paused: false,
+            pattern: None,
+            phase: 0.0,
+            gain: 0.0,
+            sample: None,
+            sample_position: None,
+        }
+    }
+
+    /// Play `sample` from its start, unless it is already playing or played
+    pub fn play_sample(&mut self, sample: Option<&Sample>) {
+        if !same_sample(self.sample.as_ref(), sample) {
+            self.sample = sample.cloned();
+            self.sample_position = Some(0.0);
+        }
+    }
+
+    /// Next value of the MegaChip sample, in [-1, 1]
+    fn next_pcm(&mut self) -> f32 {
+        let (Some(sample), Some(position)) = (&self.sample, self.sample_position) else {
+            return 0.0;
+        };
+        let Some(&value) = sample.data.get(position as usize) else {
+            self.sample_position = None;
+            return 0.0;
+        };
+        let len = sample.data.len() as f32;
+        let next = position + sample.rate as f32 / Self::SAMPLE_RATE as f32;
+        self.sample_position = match next < len {
+            true => Some(next),
+            false if sample.looping => Some(next % len),
+            false => None,
+        };
+        (value as f32 - 128.0) / 128.0
+    }
+
+    /// Gain change per sample to go from 0 to 1 in `duration`
+    fn ramp(duration: Duration) -> f32 {
+        match duration.as_secs_f32() * Self::SAMPLE_RATE as f32 {
+            samples if samples < 1.0 => 1.0,
+            samples => 1.0 / samples,
+        }
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        self.gain = match self.playing && !self.muted {
+            true => (self.gain + Self::ramp(self.settings.attack)).min(1.0),
+            false => (self.gain - Self::ramp(self.settings.release)).max(0.0),
+        };
+        // The phase goes through the whole pattern, or a single period of the tone
+        let (sample, frequency) = match &self.pattern {
+            Some(pattern) => (pattern.sample(self.phase), pattern.rate / Pattern::LEN),
+            None => (self.settings.waveform.sample(self.phase), self.settings.frequency),
+        };
+        self.phase = (self.phase + frequency / Self::SAMPLE_RATE as f32).fract();
+        let pcm = match self.muted || self.paused {
+            true => 0.0,
+            false => self.next_pcm(),
+        };
+        ((sample * self.gain + pcm) * self.settings.volume).clamp(-1.0, 1.0)
+    }
+
+    pub fn configure(&mut self, settings: AudioSettings) {
+        self.settings = settings;
+    }
+
+    pub fn toggle_mute(&mut self) {
+        self.muted = !self.muted;
+        log::info!("audio muted: {}", self.muted);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn paused_sample() {
+        let settings = AudioSettings {
+            volume: 1.0,
+            ..AudioSettings::default()
+        };
+        let mut synth = Synth::new(settings);
+        let sample = Sample {
+            rate: Synth::SAMPLE_RATE,
+            data: Arc::new([0x40, 0x80, 0xc0]),
+            looping: false,
+        };
+        synth.play_sample(Some(&sample));
+        assert_eq!(synth.next_sample(), -0.5);
+
+        synth.paused = true;
+        assert_eq!(synth.next_sample(), 0.0);
+        assert_eq!(synth.next_sample(), 0.0);
+
+        // The next frame carries on with the same sample
+        synth.paused = false;
+        synth.play_sample(Some(&sample));
+        assert_eq!(synth.next_sample(), 0.0);
+        assert_eq!(synth.next_sample(), 0.5);
+        assert_eq!(synth.next_sample(), 0.0);
+    }
+}
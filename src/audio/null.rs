@@ -0,0 +1,15 @@
+use super::{Audio, AudioSettings, Pattern};
+use crate::machine::Sample;
+
+/// Silent output, for machines without an audio device
+pub struct NullAudio;
+
+impl Audio for NullAudio {
+    fn frame(&mut self, _playing: bool, _pattern: Option<Pattern>, _sample: Option<&Sample>) {}
+
+    fn pause(&mut self) {}
+
+    fn toggle_mute(&mut self) {}
+
+    fn configure(&mut self, _settings: AudioSettings) {}
+}
@@ -0,0 +1,60 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use super::{Audio, AudioSettings, Pattern, Synth};
+use crate::machine::Sample;
+
+/// Records the sound to a WAV file, one frame worth of samples per [Audio::frame]
+///
+/// The file is finalized when dropped
+pub struct WavAudio {
+    synth: Synth,
+    writer: hound::WavWriter<BufWriter<File>>,
+}
+
+impl WavAudio {
+    const SAMPLES_PER_FRAME: u32 = Synth::SAMPLE_RATE / 60;
+
+    pub fn create(path: &Path, settings: AudioSettings) -> Result<Self, hound::Error> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: Synth::SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        Ok(Self {
+            synth: Synth::new(settings),
+            writer: hound::WavWriter::create(path, spec)?,
+        })
+    }
+}
+
+impl Audio for WavAudio {
+    fn frame(&mut self, playing: bool, pattern: Option<Pattern>, sample: Option<&Sample>) {
+        self.synth.playing = playing;
+        self.synth.paused = false;
+        self.synth.pattern = pattern;
+        self.synth.play_sample(sample);
+        for _ in 0..Self::SAMPLES_PER_FRAME {
+            let sample = (self.synth.next_sample() * i16::MAX as f32) as i16;
+            if let Err(error) = self.writer.write_sample(sample) {
+                log::error!("cannot write audio: {error}");
+                return;
+            }
+        }
+    }
+
+    /// Nothing is written while paused, the file only holds the frames run
+    fn pause(&mut self) {
+        self.synth.paused = true;
+    }
+
+    fn toggle_mute(&mut self) {
+        self.synth.toggle_mute();
+    }
+
+    fn configure(&mut self, settings: AudioSettings) {
+        self.synth.configure(settings);
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer};
+use sha1::{Digest, Sha1};
+use thiserror::Error;
+
+use crate::audio::{AudioConfig, AudioSettings};
+use crate::keymap::{Keymap, KeymapConfig};
+use crate::machine::{
+    CallStackPolicy, Machine, MachineCode, MemoryPolicy, Overflow, Palette, Platform, Quirks, Rgb, StackDepth,
+};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("cannot read config {0}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("invalid config {0}: {1}")]
+    Parse(PathBuf, toml::de::Error),
+}
+
+/// User configuration
+///
+/// Settings are layered: the defaults, then the settings recommended for the
+/// running ROM (see [crate::database]), then the top level of the file, then
+/// the section of the running ROM, then the command line
+///
+/// ```toml
+/// speed = 30
+///
+/// [keymap]
+/// layout = "azerty"
+///
+/// [audio]
+/// waveform = "square"
+///
+/// # snake.ch8
+/// [rom.06a6692c92eb8077329b6d4e59d55479d60574a8]
+/// speed = 15
+/// keymap = { ArrowUp = 0x5 }
+/// ```
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Config {
+    #[serde(flatten)]
+    pub defaults: Layer,
+    /// Overrides for a single ROM, keyed by the SHA-1 of the ROM, see [rom_hash]
+    pub rom: HashMap<String, Layer>,
+    /// Directory listed by the ROM browser, `programs` by default
+    pub rom_directory: Option<PathBuf>,
+}
+
+/// One level of settings, unset values are taken from the level below
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Layer {
+    /// Applied first, resetting the quirks, speed, call stack depth and 0NNN
+    /// handling to those of the platform
+    pub platform: Option<Platform>,
+    /// Instructions per frame
+    pub speed: Option<u32>,
+    pub quirks: QuirksConfig,
+    /// What 0NNN does: ignore, error or emulate
+    pub machine_code: Option<MachineCode>,
+    pub memory: MemoryConfig,
+    pub call_stack: CallStackConfig,
+    pub palette: PaletteConfig,
+    pub keymap: KeymapConfig,
+    pub audio: AudioConfig,
+    pub window: WindowConfig,
+}
+
+/// `[quirks]` section of the configuration, see [Quirks]
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct QuirksConfig {
+    pub shift: Option<bool>,
+    pub memory_increment: Option<bool>,
+    pub jump: Option<bool>,
+    pub vf_reset: Option<bool>,
+    pub wrap: Option<bool>,
+}
+
+/// `[memory]` section of the configuration, see [MemoryPolicy]
+///
+/// ```toml
+/// [memory]
+/// overflow = "wrap"
+/// protect_program = true
+/// ```
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct MemoryConfig {
+    pub overflow: Option<Overflow>,
+    pub protect_font: Option<bool>,
+    pub protect_program: Option<bool>,
+}
+
+/// `[call_stack]` section of the configuration, see [CallStackPolicy]
+///
+/// ```toml
+/// [call_stack]
+/// depth = "unbounded"
+/// check_returns = true
+/// ```
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct CallStackConfig {
+    pub depth: Option<StackDepth>,
+    pub check_returns: Option<bool>,
+}
+
+/// `[palette]` section of the configuration
+///
+/// ```toml
+/// [palette]
+/// foreground = "#ffcc00"
+/// background = "#996600"
+/// ```
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct PaletteConfig {
+    pub foreground: Option<HexColor>,
+    pub background: Option<HexColor>,
+}
+
+/// `[window]` section of the configuration
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct WindowConfig {
+    /// Size of a CHIP-8 pixel on screen
+    #[serde(default, deserialize_with = "deserialize_scale")]
+    pub scale: Option<f32>,
+}
+
+/// Check a window scale, which must be positive and finite to give a window size
+pub fn parse_scale(s: &str) -> Result<f32, String> {
+    let scale: f32 = s.parse().map_err(|_| format!("invalid scale {s:?}"))?;
+    check_scale(scale)
+}
+
+fn check_scale(scale: f32) -> Result<f32, String> {
+    match scale.is_finite() && scale > 0.0 {
+        true => Ok(scale),
+        false => Err(format!("scale must be a positive number, got {scale}")),
+    }
+}
+
+fn deserialize_scale<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f32>, D::Error> {
+    let scale = f32::deserialize(deserializer)?;
+    check_scale(scale).map(Some).map_err(serde::de::Error::custom)
+}
+
+/// `#rrggbb` color
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(try_from = "String")]
+pub struct HexColor(pub Rgb);
+
+impl FromStr for HexColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid color {s:?}, expected #rrggbb");
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if hex.len() != 6 {
+            return Err(invalid());
+        }
+        let value = u32::from_str_radix(hex, 16).map_err(|_| invalid())?;
+        let [_, r, g, b] = value.to_be_bytes();
+        Ok(Self([r, g, b]))
+    }
+}
+
+impl TryFrom<String> for HexColor {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Settings resolved from every [Layer]
+pub struct Settings {
+    pub platform: Platform,
+    pub speed: u32,
+    pub quirks: Quirks,
+    pub machine_code: MachineCode,
+    pub memory: MemoryPolicy,
+    pub call_stack: CallStackPolicy,
+    pub palette: Palette,
+    pub keymap: Keymap,
+    pub audio: AudioSettings,
+    pub scale: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            platform: Platform::default(),
+            speed: Machine::DEFAULT_SPEED,
+            quirks: Quirks::default(),
+            machine_code: MachineCode::default(),
+            memory: MemoryPolicy::default(),
+            call_stack: CallStackPolicy::default(),
+            palette: Palette::default(),
+            keymap: Keymap::default(),
+            audio: AudioSettings::default(),
+            scale: 20.0,
+        }
+    }
+}
+
+impl Settings {
+    /// Apply a layer on top of the current settings
+    pub fn apply(&mut self, layer: &Layer) {
+        if let Some(platform) = layer.platform {
+            self.platform = platform;
+            self.quirks = platform.quirks();
+            self.speed = platform.speed();
+            self.call_stack.depth = platform.stack_depth();
+            self.machine_code = platform.machine_code();
+        }
+
+        if let Some(speed) = layer.speed {
+            self.speed = speed;
+        }
+
+        let quirks = &layer.quirks;
+        for (value, quirk) in [
+            (quirks.shift, &mut self.quirks.shift),
+            (quirks.memory_increment, &mut self.quirks.memory_increment),
+            (quirks.jump, &mut self.quirks.jump),
+            (quirks.vf_reset, &mut self.quirks.vf_reset),
+            (quirks.wrap, &mut self.quirks.wrap),
+        ] {
+            if let Some(value) = value {
+                *quirk = value;
+            }
+        }
+
+        if let Some(machine_code) = layer.machine_code {
+            self.machine_code = machine_code;
+        }
+
+        if let Some(overflow) = layer.memory.overflow {
+            self.memory.overflow = overflow;
+        }
+        if let Some(protect) = layer.memory.protect_font {
+            self.memory.protect_font = protect;
+        }
+        if let Some(protect) = layer.memory.protect_program {
+            self.memory.protect_program = protect;
+        }
+
+        if let Some(depth) = layer.call_stack.depth {
+            self.call_stack.depth = depth;
+        }
+        if let Some(check) = layer.call_stack.check_returns {
+            self.call_stack.check_returns = check;
+        }
+
+        if let Some(HexColor(color)) = layer.palette.foreground {
+            self.palette.foreground = color;
+        }
+        if let Some(HexColor(color)) = layer.palette.background {
+            self.palette.background = color;
+        }
+
+        self.keymap.apply(&layer.keymap);
+        self.audio.apply(&layer.audio);
+
+        if let Some(scale) = layer.window.scale {
+            self.scale = scale;
+        }
+    }
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/chip-8/config.toml`, or `~/.config/chip-8/config.toml`
+    pub fn default_path() -> Option<PathBuf> {
+        let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        };
+        Some(config_home.join("chip-8").join("config.toml"))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(path).map_err(|e| Error::Read(path.into(), e))?;
+        toml::from_str(&text).map_err(|e| Error::Parse(path.into(), e))
+    }
+
+    /// Load the configuration at `path`, or at [Config::default_path] if it exists
+    pub fn find(path: Option<&Path>) -> Result<Self, Error> {
+        if let Some(path) = path {
+            return Self::load(path);
+        }
+        match Self::default_path() {
+            Some(path) if path.exists() => {
+                log::info!("loading config {}", path.display());
+                Self::load(&path)
+            }
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// Resolve the settings for the ROM with this hash, on top of the
+    /// `recommended` ones, `overrides` coming last
+    pub fn settings(&self, rom_hash: &str, recommended: &Layer, overrides: &Layer) -> Settings {
+        let mut settings = Settings::default();
+        settings.apply(recommended);
+        settings.apply(&self.defaults);
+        if let Some(rom) = self.rom.get(rom_hash) {
+            settings.apply(rom);
+        }
+        settings.apply(overrides);
+        settings
+    }
+}
+
+/// Lowercase hex SHA-1 of a ROM, used to key per-ROM settings
+pub fn rom_hash(program: &[u8]) -> String {
+    Sha1::digest(program)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
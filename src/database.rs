@@ -0,0 +1,98 @@
+//! Bundled database of known ROMs and the settings they need, see `data/roms.toml`
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::config::Layer;
+use crate::machine::Platform;
+
+const DATABASE: &str = include_str!("../data/roms.toml");
+
+#[derive(Deserialize, Debug)]
+pub struct Entry {
+    pub title: String,
+    pub author: Option<String>,
+    #[serde(flatten)]
+    pub settings: Layer,
+}
+
+pub struct Database(HashMap<String, Entry>);
+
+impl Database {
+    pub fn bundled() -> Self {
+        Self(toml::from_str(DATABASE).expect("invalid bundled ROM database"))
+    }
+
+    /// Look a ROM up by its SHA-1, see [crate::config::rom_hash]
+    pub fn get(&self, rom_hash: &str) -> Option<&Entry> {
+        self.0.get(rom_hash)
+    }
+}
+
+/// Settings recommended for a ROM by its database entry, with the platform
+/// guessed from the program when the entry is missing or does not give it
+pub fn recommended(entry: Option<&Entry>, program: &[u8]) -> Layer {
+    let mut settings = entry.map(|entry| entry.settings.clone()).unwrap_or_default();
+    if settings.platform.is_none() {
+        let platform = Platform::detect(program);
+        log::info!("detected platform: {platform}");
+        settings.platform = Some(platform);
+    }
+    settings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{rom_hash, Config};
+    use crate::machine::{MachineCode, MemoryPolicy};
+
+    /// Resolve the settings of `program` under the configuration `config`
+    fn resolve(program: &[u8], config: &str) -> crate::config::Settings {
+        let config: Config = toml::from_str(config).unwrap();
+        let database = Database::bundled();
+        let hash = rom_hash(program);
+        let recommended = recommended(database.get(&hash), program);
+        config.settings(&hash, &recommended, &Layer::default())
+    }
+
+    #[test]
+    fn database_entry_under_config() {
+        let program = include_bytes!("../programs/8-scrolling.ch8");
+        let settings = resolve(program, include_str!("../config.example.toml"));
+        assert_eq!(settings.platform, Platform::SuperChip);
+        assert!(settings.quirks.shift);
+        assert!(settings.quirks.jump);
+    }
+
+    #[test]
+    fn detected_platform_under_config() {
+        let program = [
+            0xf0, 0x00, 0x12, 0x34, // i := long 0x1234
+        ];
+        let settings = resolve(&program, include_str!("../config.example.toml"));
+        assert_eq!(settings.platform, Platform::XoChip);
+        assert_eq!(settings.speed, Platform::XoChip.speed());
+        assert!(settings.quirks.memory_increment);
+        assert!(settings.quirks.wrap);
+
+        // Values set at the top level still override the recommended ones
+        let settings = resolve(&program, "speed = 30\n[quirks]\nwrap = false");
+        assert_eq!(settings.speed, 30);
+        assert!(!settings.quirks.wrap);
+        assert!(settings.quirks.memory_increment);
+    }
+
+    #[test]
+    fn hires_machine_code_under_config() {
+        let mut program = vec![0x12, 0x60]; // jump 0x260
+        program.resize(0x60, 0);
+        program.extend([0x02, 0x30]); // two-page hires clear
+        let settings = resolve(&program, include_str!("../config.example.toml"));
+        assert_eq!(settings.platform, Platform::HiRes);
+        assert_eq!(settings.machine_code, MachineCode::Emulate);
+        assert_eq!(settings.memory, MemoryPolicy::default());
+        assert_eq!(settings.call_stack.depth, Platform::HiRes.stack_depth());
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use iced::keyboard::Key;
+use serde::Deserialize;
+
+use crate::machine;
+
+/// Physical layouts the default bindings can follow, so the 4x4 block
+/// under `1` keeps the shape of the COSMAC VIP keypad
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    #[default]
+    Qwerty,
+    Azerty,
+    Qwertz,
+}
+
+impl Layout {
+    /// Keyboard keys for the keypad, row by row
+    fn rows(self) -> [[&'static str; 4]; 4] {
+        match self {
+            Layout::Qwerty => [
+                ["1", "2", "3", "4"],
+                ["q", "w", "e", "r"],
+                ["a", "s", "d", "f"],
+                ["z", "x", "c", "v"],
+            ],
+            Layout::Azerty => [
+                ["&", "é", "\"", "'"],
+                ["a", "z", "e", "r"],
+                ["q", "s", "d", "f"],
+                ["w", "x", "c", "v"],
+            ],
+            Layout::Qwertz => [
+                ["1", "2", "3", "4"],
+                ["q", "w", "e", "r"],
+                ["a", "s", "d", "f"],
+                ["y", "x", "c", "v"],
+            ],
+        }
+    }
+}
+
+/// `[keymap]` section of the configuration
+///
+/// ```toml
+/// [keymap]
+/// layout = "azerty"
+/// Space = 0x5
+/// ArrowUp = 0x5
+///
+/// [keymap.second]
+/// i = 0x2
+/// ```
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct KeymapConfig {
+    pub layout: Option<Layout>,
+    /// Keyboard key name to key of the second keypad of the CHIP-8X, which
+    /// has no default bindings
+    #[serde(default)]
+    pub second: HashMap<String, machine::Key>,
+    /// Keyboard key name to keypad key, see [key_name]
+    #[serde(flatten)]
+    pub keys: HashMap<String, machine::Key>,
+}
+
+pub struct Keymap {
+    bindings: HashMap<String, machine::Key>,
+    /// Bindings of the second keypad
+    second: HashMap<String, machine::Key>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::new(Layout::default())
+    }
+}
+
+impl Keymap {
+    pub fn new(layout: Layout) -> Self {
+        let bindings = layout
+            .rows()
+            .into_iter()
+            .flatten()
+            .zip(machine::Keypad::LAYOUT.into_iter().flatten())
+            .map(|(name, key)| (name.to_string(), key))
+            .collect();
+        Self {
+            bindings,
+            second: HashMap::new(),
+        }
+    }
+
+    /// Apply a configuration on top of the current bindings
+    ///
+    /// A `layout` replaces the bindings of the first keypad, then the
+    /// explicit keys are added
+    pub fn apply(&mut self, config: &KeymapConfig) {
+        if let Some(layout) = config.layout {
+            self.bindings = Self::new(layout).bindings;
+        }
+        bind(&mut self.bindings, &config.keys);
+        bind(&mut self.second, &config.second);
+    }
+
+    pub fn get(&self, key: &Key) -> Option<machine::Key> {
+        self.bindings.get(&key_name(key)?).copied()
+    }
+
+    /// Key of the second keypad bound to `key`
+    pub fn get_second(&self, key: &Key) -> Option<machine::Key> {
+        self.second.get(&key_name(key)?).copied()
+    }
+}
+
+fn bind(bindings: &mut HashMap<String, machine::Key>, keys: &HashMap<String, machine::Key>) {
+    for (name, &key) in keys {
+        if key as usize >= machine::Keypad::KEY_COUNT {
+            log::warn!("keymap: ignoring {name} = {key:#x}, not a keypad key");
+            continue;
+        }
+        bindings.insert(normalize(name), key);
+    }
+}
+
+/// Name of a keyboard key as written in the configuration
+///
+/// Characters are lowercased (`"q"`, `"&"`), named keys use their iced
+/// name (`"ArrowUp"`, `"Space"`, `"Enter"`)
+pub fn key_name(key: &Key) -> Option<String> {
+    match key {
+        Key::Character(c) => Some(c.to_lowercase()),
+        Key::Named(named) => Some(format!("{named:?}")),
+        Key::Unidentified => None,
+    }
+}
+
+fn normalize(name: &str) -> String {
+    match name.chars().count() {
+        1 => name.to_lowercase(),
+        _ => name.to_string(),
+    }
+}
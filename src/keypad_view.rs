@@ -0,0 +1,55 @@
+//! Clickable on-screen hex keypad, laid out like the COSMAC VIP keypad
+
+use iced::widget::{center, column, container, mouse_area, row, text};
+use iced::{Border, Color, Element, Length};
+
+use crate::machine::{Key, Keypad};
+use crate::Message;
+
+const KEY_SIZE: f32 = 56.0;
+const SPACING: f32 = 6.0;
+const PADDING: f32 = 12.0;
+
+/// Width taken by the keypad next to the screen
+pub const WIDTH: f32 = 4.0 * KEY_SIZE + 3.0 * SPACING + 2.0 * PADDING;
+
+pub fn view(keypad: &Keypad) -> Element<'_, Message> {
+    let rows = Keypad::LAYOUT.map(|keys| {
+        row(keys.map(|key| key_button(key, keypad.pressed(key))))
+            .spacing(SPACING)
+            .into()
+    });
+
+    center(column(rows).spacing(SPACING))
+        .width(Length::Fixed(WIDTH))
+        .padding(PADDING)
+        .into()
+}
+
+fn key_button<'a>(key: Key, pressed: bool) -> Element<'a, Message> {
+    let (background, foreground) = match pressed {
+        true => (Color::WHITE, Color::BLACK),
+        false => (Color::from_rgb8(0x30, 0x30, 0x30), Color::WHITE),
+    };
+
+    let label = container(text(format!("{key:X}")).size(24))
+        .center(Length::Fixed(KEY_SIZE))
+        .style(move |_theme| container::Style {
+            text_color: Some(foreground),
+            background: Some(background.into()),
+            border: Border {
+                color: Color::from_rgb8(0x80, 0x80, 0x80),
+                width: 1.0,
+                radius: 4.0.into(),
+            },
+            ..container::Style::default()
+        });
+
+    // Releasing outside of the key never reaches `on_release`, leaving the
+    // key stuck, so leaving the key releases it as well
+    mouse_area(label)
+        .on_press(Message::KeyPadPressed(key))
+        .on_release(Message::KeyPadReleased(key))
+        .on_exit(Message::KeyPadReleased(key))
+        .into()
+}
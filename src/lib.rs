@@ -0,0 +1,3 @@
+//! CHIP-8 interpreter, the emulator around it lives in `main.rs`
+
+pub mod machine;
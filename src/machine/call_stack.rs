@@ -1,47 +1,162 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Deserialize;
 use thiserror::Error;
 
-use super::Address;
+use super::{Address, INSTRUCTION_SIZE};
 
 pub struct CallStack {
-    stack: [Address; 12],
-    top: usize,
+    /// Oldest first
+    frames: Vec<Frame>,
+    pub policy: Policy,
+}
+
+/// Subroutine called by a 2NNN instruction, and not returned from yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    /// Address of the 2NNN instruction
+    pub call: Address,
+    /// NNN, where the subroutine starts
+    pub subroutine: Address,
+}
+
+impl Frame {
+    /// Address 00EE returns to, after the call
+    pub fn return_address(self) -> Address {
+        self.call + INSTRUCTION_SIZE
+    }
 }
 
+/// Subroutines the stack holds before overflowing
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(try_from = "DepthValue")]
+pub enum Depth {
+    /// 12 levels, like on the COSMAC VIP
+    #[default]
+    Vip,
+    /// 16 levels, like on the HP48 SUPER-CHIP
+    Schip,
+    /// [Depth::UNBOUNDED_LIMIT] levels, only there to stop runaway recursions
+    /// before they take all the memory of the host
+    Unbounded,
+}
+
+impl Depth {
+    pub const ALL: [Depth; 3] = [Depth::Vip, Depth::Schip, Depth::Unbounded];
+    /// Levels of [Depth::Unbounded], far more than any program nests
+    pub const UNBOUNDED_LIMIT: usize = 0x10000;
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Depth::Vip => "12",
+            Depth::Schip => "16",
+            Depth::Unbounded => "unbounded",
+        }
+    }
+
+    pub fn limit(self) -> usize {
+        match self {
+            Depth::Vip => 12,
+            Depth::Schip => 16,
+            Depth::Unbounded => Self::UNBOUNDED_LIMIT,
+        }
+    }
+}
+
+impl fmt::Display for Depth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Depth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|depth| depth.name() == s)
+            .ok_or_else(|| format!("unknown call stack depth {s:?}, expected 12, 16 or unbounded"))
+    }
+}
+
+/// `depth = 16` or `depth = "unbounded"` in the configuration
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DepthValue {
+    Levels(u64),
+    Name(String),
+}
+
+impl TryFrom<DepthValue> for Depth {
+    type Error = String;
+
+    fn try_from(value: DepthValue) -> Result<Self, String> {
+        match value {
+            DepthValue::Levels(levels) => levels.to_string().parse(),
+            DepthValue::Name(name) => name.parse(),
+        }
+    }
+}
+
+/// How calls and returns are checked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Policy {
+    pub depth: Depth,
+    /// 00EE fails when the instruction before the return address is not a
+    /// 2NNN call anymore, catching programs overwriting a caller
+    pub check_returns: bool,
+}
 
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("overflow")]
-    Overflow,
-    #[error("underflow")]
+    #[error("call stack overflow, {0} subroutines deep")]
+    Overflow(usize),
+    #[error("return with an empty call stack")]
     Underflow,
+    #[error("return to {return_address:#05x}, which does not follow a call")]
+    NotAfterCall { return_address: Address },
+}
+
+impl Default for CallStack {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CallStack {
     pub fn new() -> Self {
         Self {
-            stack: [0; 12],
-            top: 0,
+            frames: Vec::new(),
+            policy: Policy::default(),
         }
     }
 
-    pub fn push(&mut self, addr: Address) -> Result<(), Error> {
-        match self.stack.get_mut(self.top) {
-            Some(top) => {
-                *top = addr;
-                self.top += 1;
-                Ok(())
-            }
-            None => Err(Error::Overflow),
+    pub fn push(&mut self, frame: Frame) -> Result<(), Error> {
+        if self.frames.len() >= self.policy.depth.limit() {
+            return Err(Error::Overflow(self.frames.len()));
         }
+        self.frames.push(frame);
+        Ok(())
     }
 
-    pub fn pop(&mut self) -> Result<Address, Error> {
-        match self.top.checked_sub(1) {
-            Some(result) => {
-                self.top = result;
-                Ok(self.stack[self.top])
-            }
-            None => Err(Error::Underflow),
-        }
+    /// Number of subroutines on the stack
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Frames of the subroutines called, oldest first
+    pub fn frames(&self) -> impl DoubleEndedIterator<Item = Frame> + ExactSizeIterator + '_ {
+        self.frames.iter().copied()
+    }
+
+    /// Frame of the subroutine running
+    pub fn top(&self) -> Option<Frame> {
+        self.frames.last().copied()
+    }
+
+    pub fn pop(&mut self) -> Result<Frame, Error> {
+        self.frames.pop().ok_or(Error::Underflow)
     }
 }
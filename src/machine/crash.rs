@@ -0,0 +1,115 @@
+//! Report of a failing instruction, with the state of the machine when it failed
+//!
+//! ```text
+//! write of 3 bytes at 0xffe goes past the end of memory at 0x1000
+//!   at       204 main+4  bcd.8o:3
+//!   opcode   F033  binary_encode v0
+//!   V0-V7    FF 00 00 00 00 00 00 00
+//!   V8-VF    00 00 00 00 00 00 00 00
+//!   I        FFE
+//!   timers   DT 00  ST 00
+//!   stack    depth 0
+//! ```
+
+use std::fmt;
+
+use super::instruction::Instruction;
+use super::{Address, Frame, Machine, TickError};
+
+#[derive(Debug)]
+pub struct Crash {
+    pub error: TickError,
+    /// Address of the failing instruction
+    pub ip_register: Address,
+    /// `ip_register` relative to the closest label, and its source line when
+    /// known, when there are symbols
+    pub location: Option<String>,
+    pub opcode: Option<u16>,
+    pub instruction: Option<Instruction>,
+    pub registers: [u8; 16],
+    pub i_register: Address,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    /// Return addresses, oldest first
+    pub stack: Vec<Address>,
+}
+
+impl Crash {
+    /// `error` raised by the instruction at [Machine::ip_register]
+    pub fn new(machine: &Machine, error: TickError) -> Self {
+        let ip = machine.ip_register;
+        let opcode = machine.memory.opcode_at(ip);
+        let instruction = opcode.and_then(|opcode| {
+            let [a, b] = opcode.to_be_bytes();
+            Instruction::decode([a >> 4, a & 0xf, b >> 4, b & 0xf], machine.platform)
+        });
+        let symbols = &machine.symbols;
+        let location = (!symbols.is_empty()).then(|| match symbols.line(ip) {
+            Some(line) => format!("{}  {line}", symbols.locate(ip)),
+            None => symbols.locate(ip),
+        });
+        Self {
+            error,
+            ip_register: ip,
+            location,
+            opcode,
+            instruction,
+            registers: machine.registers,
+            i_register: machine.i_register,
+            delay_timer: machine.delay_timer,
+            sound_timer: machine.sound_timer,
+            stack: machine
+                .call_stack
+                .frames()
+                .map(Frame::return_address)
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for Crash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.error)?;
+        write!(f, "  at       {:03X}", self.ip_register)?;
+        if let Some(location) = &self.location {
+            write!(f, " {location}")?;
+        }
+        match (self.opcode, self.instruction) {
+            (Some(opcode), Some(instruction)) => {
+                write!(f, "\n  opcode   {opcode:04X}  {instruction}")?
+            }
+            (Some(opcode), None) => write!(f, "\n  opcode   {opcode:04X}  unknown")?,
+            (None, _) => write!(f, "\n  opcode   out of memory")?,
+        }
+        for (row, registers) in self.registers.chunks(8).enumerate() {
+            let first = row * 8;
+            write!(f, "\n  V{first:X}-V{:X}    ", first + 7)?;
+            for (offset, register) in registers.iter().enumerate() {
+                let separator = if offset == 0 { "" } else { " " };
+                write!(f, "{separator}{register:02X}")?;
+            }
+        }
+        write!(f, "\n  I        {:03X}", self.i_register)?;
+        write!(
+            f,
+            "\n  timers   DT {:02X}  ST {:02X}",
+            self.delay_timer, self.sound_timer
+        )?;
+        write!(f, "\n  stack    depth {}", self.stack.len())?;
+        if !self.stack.is_empty() {
+            let addresses: Vec<_> = self
+                .stack
+                .iter()
+                .map(|addr| format!("{addr:03X}"))
+                .collect();
+            write!(f, ": {}", addresses.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Crash {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
@@ -1,11 +1,17 @@
+use rand::Rng;
+
 use super::{
-    memory, Address, Machine, Memory, Register, TickError, TickFlow, TickResult, INSTRUCTION_SIZE,
+    call_stack, machine_code, memory, Address, AudioPattern, Blend, ColourBoard, Frame, Framebuffer, Machine,
+    MachineCode, Memory, Register, Sample, TickError, TickFlow, TickResult, INSTRUCTION_SIZE,
 };
 
 impl Machine {
     /// 2NNN: Execute subroutine starting at address NNN
     pub fn execute_subroutine(&mut self, addr: Address) -> TickResult {
-        self.call_stack.push(self.ip_register + INSTRUCTION_SIZE)?;
+        self.call_stack.push(Frame {
+            call: self.ip_register,
+            subroutine: addr,
+        })?;
         Ok(TickFlow::GoTo(addr))
     }
 
@@ -44,8 +50,14 @@ impl Machine {
 
     /// 00EE: Return from a subroutine
     pub fn return_from_subroutine(&mut self) -> TickResult {
-        let addr = self.call_stack.pop()?;
-        Ok(TickFlow::GoTo(addr))
+        let frame = self.call_stack.top().ok_or(call_stack::Error::Underflow)?;
+        let follows_call = || self.memory.opcode_at(frame.call).is_some_and(|opcode| opcode >> 12 == 0x2);
+        if self.call_stack.policy.check_returns && !follows_call() {
+            let return_address = frame.return_address();
+            return Err(call_stack::Error::NotAfterCall { return_address }.into());
+        }
+        self.call_stack.pop()?;
+        Ok(TickFlow::GoTo(frame.return_address()))
     }
 
     /// 1NNN: Jump to address NNN
@@ -53,9 +65,20 @@ impl Machine {
         Ok(TickFlow::GoTo(addr))
     }
 
-    /// 0NNN: Execute machine language subroutine at address NNN
+    /// 0NNN: Execute machine language subroutine at address NNN, see [MachineCode]
     pub fn jump_to_machine_code(&mut self, addr: Address) -> TickResult {
-        Err(TickError::Unimplemented(format!("Cannot jump to machine code at {addr:#x}")))
+        match self.machine_code {
+            MachineCode::Ignore => Ok(TickFlow::Advance),
+            MachineCode::Error => Err(TickError::Unimplemented(format!(
+                "Cannot jump to machine code at {addr:#x}"
+            ))),
+            MachineCode::Emulate => match machine_code::routine(addr) {
+                Some(routine) => (routine.run)(self),
+                None => Err(TickError::Unimplemented(format!(
+                    "No machine code routine emulated at {addr:#x}"
+                ))),
+            },
+        }
     }
 
     /// 6XNN: Store number NN in register VX
@@ -79,21 +102,39 @@ impl Machine {
     /// 8XY1: Set VX to VX OR VY
     pub fn or(&mut self, x: Register, y: Register) -> TickResult {
         *self.register_mut(x) |= self.register(y);
+        self.reset_vf();
         Ok(TickFlow::Advance)
     }
 
     /// 8XY2: Set VX to VX AND VY
     pub fn and(&mut self, x: Register, y: Register) -> TickResult {
         *self.register_mut(x) &= self.register(y);
+        self.reset_vf();
         Ok(TickFlow::Advance)
     }
 
     /// 8XY3: Set VX to VX XOR VY
     pub fn xor(&mut self, x: Register, y: Register) -> TickResult {
         *self.register_mut(x) ^= self.register(y);
+        self.reset_vf();
         Ok(TickFlow::Advance)
     }
 
+    /// VF is reset by logical operations with the [Quirks::vf_reset] quirk
+    fn reset_vf(&mut self) {
+        if self.quirks.vf_reset {
+            *self.register_mut(0xF) = 0;
+        }
+    }
+
+    /// Register shifted by 8XY6 and 8XYE: VY, or VX with the [Quirks::shift] quirk
+    fn shift_source(&self, x: Register, y: Register) -> u8 {
+        match self.quirks.shift {
+            true => self.register(x),
+            false => self.register(y),
+        }
+    }
+
     /// 8XY4: Add the value of register VY to register VX
     /// Set VF to 01 if a carry occurs
     /// Set VF to 00 if a carry does not occur
@@ -118,8 +159,9 @@ impl Machine {
     /// Set register VF to the least significant bit prior to the shift
     /// VY is unchanged
     pub fn shift_right(&mut self, x: Register, y: Register) -> TickResult {
-        let carry = self.register(y) & 1;
-        *self.register_mut(x) = self.register(y) >> 1;
+        let value = self.shift_source(x, y);
+        let carry = value & 1;
+        *self.register_mut(x) = value >> 1;
         *self.register_mut(0xF) = carry;
         Ok(TickFlow::Advance)
     }
@@ -138,9 +180,10 @@ impl Machine {
     /// Set register VF to the most significant bit prior to the shift
     /// VY is unchanged
     pub fn shift_left(&mut self, x: Register, y: Register) -> TickResult {
+        let value = self.shift_source(x, y);
         // Remove all 7 less significant bits, leaving 8th at least significant position
-        let carry = self.register(y) >> 7;
-        *self.register_mut(x) = self.register(y) << 1;
+        let carry = value >> 7;
+        *self.register_mut(x) = value << 1;
         *self.register_mut(0xF) = carry;
         Ok(TickFlow::Advance)
     }
@@ -161,13 +204,18 @@ impl Machine {
     }
 
     /// BNNN: Jump to address NNN + V0
+    /// With the [Quirks::jump] quirk, jump to address XNN + VX
     pub fn jump_to_offset(&mut self, reference: Address) -> TickResult {
-        Ok(TickFlow::GoTo(reference + self.register(0) as Address))
+        let offset = match self.quirks.jump {
+            true => self.register((reference >> 8) as Register),
+            false => self.register(0),
+        };
+        Ok(TickFlow::GoTo(reference + offset as Address))
     }
 
     /// CNNN: Set VX to a random number with a mask of NN
     pub fn store_random(&mut self, x: Register, mask: u8) -> TickResult {
-        *self.register_mut(x) = rand::random::<u8>() & mask;
+        *self.register_mut(x) = self.rng.gen::<u8>() & mask;
         Ok(TickFlow::Advance)
     }
 
@@ -177,11 +225,18 @@ impl Machine {
         let x = self.register(x) as usize;
         let y = self.register(y) as usize;
 
-        let sprite = self
-            .memory
-            .range_mut(self.i_register..self.i_register + line_count as Address)?;
+        let indexed = self.screen.framebuffer().filter(|framebuffer| framebuffer.enabled());
+        if let Some(len) = indexed.map(|framebuffer| framebuffer.sprite_width * framebuffer.sprite_height) {
+            // MegaChip sprites are sized by 03NN and 04NN instead
+            let sprite = self.memory.read_span(self.i_register, len as Address)?;
+            let collision_found = self.screen.draw_indexed_sprite(x, y, &sprite);
+            *self.register_mut(0xf) = u8::from(collision_found);
+            return Ok(TickFlow::Advance);
+        }
 
-        let collision_found = self.screen.draw_sprite(x, y, sprite);
+        let sprite = self.memory.read_span(self.i_register, line_count as Address)?;
+
+        let collision_found = self.screen.draw_sprite(x, y, &sprite, self.quirks.wrap);
         *self.register_mut(0xf) = u8::from(collision_found);
         Ok(TickFlow::Advance)
     }
@@ -213,11 +268,12 @@ impl Machine {
     }
 
     /// FX0A: Wait for a keypress and store the result in register VX
+    /// The key is stored once it is released
     pub fn wait_for_keypress(&mut self, x: Register) -> TickResult {
         log::trace!("wait_for_key_press");
-        match self.keypad.just_released() {
+        match self.keypad.wait_for_key() {
             Some(key) => {
-                log::debug!("Key just released: {key}");
+                log::debug!("Key pressed and released: {key}");
                 *self.register_mut(x) = key;
                 Ok(TickFlow::Advance)
             }
@@ -239,14 +295,14 @@ impl Machine {
 
     /// FX1E: Add the value stored in register VX to register I
     pub fn add_to_i(&mut self, x: Register) -> TickResult {
-        let value = u16::from(self.register(x));
+        let value = Address::from(self.register(x));
         self.i_register = self.i_register.wrapping_add(value);
         Ok(TickFlow::Advance)
     }
 
     /// FX29: Set I to the memory address of the sprite data corresponding to the hexadecimal digit stored in register VX
     pub fn store_digit_location(&mut self, x: Register) -> TickResult {
-        self.i_register = Memory::FONT_LOCATION + self.register(x) as u16 * memory::GLYPH_SIZE;
+        self.i_register = Memory::FONT_LOCATION + Address::from(self.register(x)) * memory::GLYPH_SIZE;
         Ok(TickFlow::Advance)
     }
 
@@ -255,36 +311,188 @@ impl Machine {
     pub fn store_binary_coded(&mut self, x: Register) -> TickResult {
         let n = self.register(x);
 
-        *self.memory.get_mut(self.i_register)? = n / 100;
-        *self
-            .memory
-            .get_mut(self.i_register + 1)? = (n / 10) % 10;
-        *self
-            .memory
-            .get_mut(self.i_register + 2)? = n % 10;
+        self.memory
+            .write_span(self.i_register, &[n / 100, (n / 10) % 10, n % 10])?;
 
         Ok(TickFlow::Advance)
     }
 
     /// FX55: Store the values of registers V0 to VX inclusive in memory starting at address I
-    /// I is set to I + X + 1 after operation
+    /// I is set to I + X + 1 after operation with the [Quirks::memory_increment] quirk
     pub fn store_registers(&mut self, x: Register) -> TickResult {
-        for i in 0..=x {
-            *self
-                .memory
-                .get_mut(self.i_register + i as Address)? = self.register(i);
-            // self.i_register += 1;
-        }
+        self.memory
+            .write_span(self.i_register, &self.registers[..=x as usize])?;
+        self.increment_i(x);
         Ok(TickFlow::Advance)
     }
 
     /// FX65: Fill registers V0 to VX inclusive with the values stored in memory starting at address I
-    /// I is set to I + X + 1 after operation
+    /// I is set to I + X + 1 after operation with the [Quirks::memory_increment] quirk
     pub fn load_registers(&mut self, x: Register) -> TickResult {
-        for i in 0..=x {
-            *self.register_mut(i) = self.memory.get(self.i_register + i as Address)?;
-            // self.i_register += 1;
+        let values = self.memory.read_span(self.i_register, x as Address + 1)?;
+        self.registers[..=x as usize].copy_from_slice(&values);
+        self.increment_i(x);
+        Ok(TickFlow::Advance)
+    }
+
+    fn increment_i(&mut self, x: Register) {
+        if self.quirks.memory_increment {
+            self.i_register = self.i_register.wrapping_add(x as Address + 1);
+        }
+    }
+
+    /// F002: Store the 16 bytes starting at address I in the audio pattern buffer (XO-CHIP)
+    pub fn load_audio_pattern(&mut self) -> TickResult {
+        let mut pattern = AudioPattern::default();
+        let len = pattern.len() as Address;
+        pattern.copy_from_slice(&self.memory.read_span(self.i_register, len)?);
+        self.audio_pattern = Some(pattern);
+        Ok(TickFlow::Advance)
+    }
+
+    /// FX3A: Set the audio pattern playback rate to 4000*2^((VX-64)/48) Hz (XO-CHIP)
+    pub fn set_pitch(&mut self, x: Register) -> TickResult {
+        self.pitch = self.register(x);
+        Ok(TickFlow::Advance)
+    }
+
+    /// 02A0: Move to the next background colour (CHIP-8X)
+    pub fn cycle_background(&mut self) -> TickResult {
+        self.screen.cycle_background();
+        Ok(TickFlow::Advance)
+    }
+
+    /// BXY0: Set the colour of zones of 8x4 pixels to the value of V(X+1) (CHIP-8X)
+    /// The low nibbles of VX and VY are the column and row of the first zone,
+    /// their high nibbles the number of zones added to the right and below
+    pub fn set_zone_colour(&mut self, x: Register, y: Register) -> TickResult {
+        let (vx, vy) = (self.register(x) as usize, self.register(y) as usize);
+        let colour = self.register((x + 1) & 0xf);
+        let height = ColourBoard::ZONE_HEIGHT;
+        let (column, row) = (vx & 0xf, (vy & 0xf) * height);
+        let (columns, rows) = ((vx >> 4) + 1, ((vy >> 4) + 1) * height);
+        self.screen.set_colour(column, row, columns, rows, colour);
+        Ok(TickFlow::Advance)
+    }
+
+    /// BXYN: Set the colour of N rows from VY, in the 8 pixels wide column
+    /// holding VX, to the value of V(X+1) (CHIP-8X)
+    pub fn set_line_colour(&mut self, x: Register, y: Register, count: u8) -> TickResult {
+        let (vx, vy) = (self.register(x) as usize, self.register(y) as usize);
+        let colour = self.register((x + 1) & 0xf);
+        let column = vx / ColourBoard::CELL_WIDTH;
+        self.screen.set_colour(column, vy, 1, count as usize, colour);
+        Ok(TickFlow::Advance)
+    }
+
+    /// EXF2: Skip the following instruction if the key of the value of VX is pressed on the second keypad (CHIP-8X)
+    pub fn skip_if_key_2_pressed(&mut self, x: Register) -> TickResult {
+        if self.keypad_2.pressed(self.register(x)) {
+            Ok(TickFlow::Skip)
+        } else {
+            Ok(TickFlow::Advance)
+        }
+    }
+
+    /// EXF5: Skip the following instruction if the key of the value of VX is not pressed on the second keypad (CHIP-8X)
+    pub fn skip_if_key_2_not_pressed(&mut self, x: Register) -> TickResult {
+        if self.keypad_2.pressed(self.register(x)) {
+            Ok(TickFlow::Advance)
+        } else {
+            Ok(TickFlow::Skip)
+        }
+    }
+
+    /// 0010: Turn off the MegaChip mode, back to the screen of the platform (MegaChip)
+    pub fn disable_megachip(&mut self) -> TickResult {
+        let (width, height) = self.platform.screen_size();
+        self.screen.disable_framebuffer(width, height);
+        Ok(TickFlow::Advance)
+    }
+
+    /// 0011: Turn on the MegaChip mode, drawing to the 256x192 framebuffer (MegaChip)
+    pub fn enable_megachip(&mut self) -> TickResult {
+        self.screen.enable_framebuffer();
+        Ok(TickFlow::Advance)
+    }
+
+    /// 01NN NNNN: Set I to the 24-bit address NNNNNN, its low 16 bits being
+    /// the 2 bytes after the instruction, which are skipped (MegaChip)
+    pub fn store_long_addr(&mut self, high: u8) -> TickResult {
+        let low_addr = self.ip_register.wrapping_add(INSTRUCTION_SIZE);
+        let low = self.memory.read_span(low_addr, 2)?;
+        self.i_register = u32::from_be_bytes([0, high, low[0], low[1]]);
+        Ok(TickFlow::GoTo(low_addr.wrapping_add(2)))
+    }
+
+    /// 02NN: Load NN ARGB colours from I into the palette, from index 1 (MegaChip)
+    pub fn load_palette(&mut self, count: u8) -> TickResult {
+        let colours = self.memory.read_span(self.i_register, Address::from(count) * 4)?;
+        if let Some(framebuffer) = self.screen.framebuffer_mut() {
+            for (entry, argb) in framebuffer.palette[1..].iter_mut().zip(colours.chunks_exact(4)) {
+                *entry = [argb[1], argb[2], argb[3]];
+            }
+        }
+        Ok(TickFlow::Advance)
+    }
+
+    /// 03NN: Set the width of sprites to NN, 256 for 0 (MegaChip)
+    pub fn set_sprite_width(&mut self, width: u8) -> TickResult {
+        if let Some(framebuffer) = self.screen.framebuffer_mut() {
+            framebuffer.sprite_width = sprite_size(width);
+        }
+        Ok(TickFlow::Advance)
+    }
+
+    /// 04NN: Set the height of sprites to NN, 256 for 0 (MegaChip)
+    pub fn set_sprite_height(&mut self, height: u8) -> TickResult {
+        if let Some(framebuffer) = self.screen.framebuffer_mut() {
+            framebuffer.sprite_height = sprite_size(height);
         }
         Ok(TickFlow::Advance)
     }
+
+    /// 05NN: Set the opacity of the screen to NN, to fade it out (MegaChip)
+    pub fn set_screen_alpha(&mut self, alpha: u8) -> TickResult {
+        if let Some(framebuffer) = self.screen.framebuffer_mut() {
+            framebuffer.alpha = alpha;
+        }
+        Ok(TickFlow::Advance)
+    }
+
+    /// 060N: Play the digitised sound at I, looping for N = 0 (MegaChip)
+    pub fn play_sample(&mut self, looping: bool) -> TickResult {
+        self.sample = Some(Sample::read(&self.memory, self.i_register, looping)?);
+        Ok(TickFlow::Advance)
+    }
+
+    /// 0700: Stop the digitised sound (MegaChip)
+    pub fn stop_sample(&mut self) -> TickResult {
+        self.sample = None;
+        Ok(TickFlow::Advance)
+    }
+
+    /// 080N: Set how sprites mix with the colours under them (MegaChip)
+    pub fn set_blend(&mut self, blend: Blend) -> TickResult {
+        if let Some(framebuffer) = self.screen.framebuffer_mut() {
+            framebuffer.blend = blend;
+        }
+        Ok(TickFlow::Advance)
+    }
+
+    /// 09NN: Make sprites drawn over pixels of palette index NN collide (MegaChip)
+    pub fn set_collision_colour(&mut self, index: u8) -> TickResult {
+        if let Some(framebuffer) = self.screen.framebuffer_mut() {
+            framebuffer.collision_index = index;
+        }
+        Ok(TickFlow::Advance)
+    }
+}
+
+/// Sprite width or height set by 03NN and 04NN
+fn sprite_size(size: u8) -> usize {
+    match size {
+        0 => Framebuffer::WIDTH,
+        size => size as usize,
+    }
 }
@@ -1,8 +1,8 @@
 use super::{u16_from_nibbles, u8_from_nibbles};
-use super::{Address, Register};
+use super::{Address, Blend, Platform, Register, Symbols, INSTRUCTION_SIZE};
 use std::fmt;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
     ClearScreen,
     ReturnFromSubroutine,
@@ -39,17 +39,82 @@ pub enum Instruction {
     StoreBinaryCoded(Register),
     StoreRegisters(Register),
     LoadRegisters(Register),
+    LoadAudioPattern,
+    SetPitch(Register),
+    /// CHIP-8X
+    CycleBackground,
+    /// CHIP-8X
+    SetZoneColour(Register, Register),
+    /// CHIP-8X
+    SetLineColour(Register, Register, u8),
+    /// CHIP-8X, second keypad
+    SkipIfKey2Pressed(Register),
+    /// CHIP-8X, second keypad
+    SkipIfKey2NotPressed(Register),
+    /// MegaChip
+    DisableMegaChip,
+    /// MegaChip
+    EnableMegaChip,
+    /// MegaChip, the high byte of I, the next 2 bytes are the low ones
+    StoreLongAddr(u8),
+    /// MegaChip
+    LoadPalette(u8),
+    /// MegaChip
+    SetSpriteWidth(u8),
+    /// MegaChip
+    SetSpriteHeight(u8),
+    /// MegaChip
+    SetScreenAlpha(u8),
+    /// MegaChip, looping or played once
+    PlaySample(bool),
+    /// MegaChip
+    StopSample,
+    /// MegaChip
+    SetBlend(Blend),
+    /// MegaChip
+    SetCollisionColour(u8),
 }
 
 impl Instruction {
-    pub fn decode(nibbles: [u8; 4]) -> Option<Self> {
+    /// Instruction of `platform` with these nibbles, if any
+    pub fn decode(nibbles: [u8; 4], platform: Platform) -> Option<Self> {
         use Instruction::*;
+        if platform == Platform::Chip8X {
+            match nibbles {
+                [0, 2, 0xa, 0] => return Some(CycleBackground),
+                [0xb, x, y, 0] => return Some(SetZoneColour(x, y)),
+                [0xb, x, y, n] => return Some(SetLineColour(x, y, n)),
+                [0xe, x, 0xf, 2] => return Some(SkipIfKey2Pressed(x)),
+                [0xe, x, 0xf, 5] => return Some(SkipIfKey2NotPressed(x)),
+                _ => {}
+            }
+        }
+        if platform == Platform::MegaChip {
+            match nibbles {
+                [0, 0, 1, 0] => return Some(DisableMegaChip),
+                [0, 0, 1, 1] => return Some(EnableMegaChip),
+                [0, 1, a, b] => return Some(StoreLongAddr(u8_from_nibbles(a, b))),
+                [0, 2, a, b] => return Some(LoadPalette(u8_from_nibbles(a, b))),
+                [0, 3, a, b] => return Some(SetSpriteWidth(u8_from_nibbles(a, b))),
+                [0, 4, a, b] => return Some(SetSpriteHeight(u8_from_nibbles(a, b))),
+                [0, 5, a, b] => return Some(SetScreenAlpha(u8_from_nibbles(a, b))),
+                [0, 6, 0, n @ (0 | 1)] => return Some(PlaySample(n == 0)),
+                [0, 7, 0, 0] => return Some(StopSample),
+                [0, 8, 0, n] => {
+                    if let Some(blend) = Blend::from_code(n) {
+                        return Some(SetBlend(blend));
+                    }
+                }
+                [0, 9, a, b] => return Some(SetCollisionColour(u8_from_nibbles(a, b))),
+                _ => {}
+            }
+        }
         Some(match nibbles {
             [0, 0, 0xe, 0] => ClearScreen,
             [0, 0, 0xe, 0xe] => ReturnFromSubroutine,
-            [0, a, b, c] => JumpToMachineCode(u16_from_nibbles(a, b, c)),
-            [1, a, b, c] => JumpTo(u16_from_nibbles(a, b, c)),
-            [2, a, b, c] => ExecuteSubroutine(u16_from_nibbles(a, b, c)),
+            [0, a, b, c] => JumpToMachineCode(u16_from_nibbles(a, b, c).into()),
+            [1, a, b, c] => JumpTo(u16_from_nibbles(a, b, c).into()),
+            [2, a, b, c] => ExecuteSubroutine(u16_from_nibbles(a, b, c).into()),
             [3, x, a, b] => SkipEqTo(x, u8_from_nibbles(a, b)),
             [4, x, a, b] => SkipNeqTo(x, u8_from_nibbles(a, b)),
             [5, x, y, 0] => SkipEq(x, y),
@@ -65,8 +130,8 @@ impl Instruction {
             [8, x, y, 7] => SubRegisterReverse(x, y),
             [8, x, y, 0xe] => ShiftLeft(x, y),
             [9, x, y, 0] => SkipNeq(x, y),
-            [0xa, a, b, c] => StoreAddr(u16_from_nibbles(a, b, c)),
-            [0xb, a, b, c] => JumpToOffset(u16_from_nibbles(a, b, c)),
+            [0xa, a, b, c] => StoreAddr(u16_from_nibbles(a, b, c).into()),
+            [0xb, a, b, c] => JumpToOffset(u16_from_nibbles(a, b, c).into()),
             [0xc, x, a, b] => StoreRandom(x, u8_from_nibbles(a, b)),
             [0xd, x, y, a] => DrawSprite(x, y, a),
             [0xe, x, 9, 0xe] => SkipIfKeyPressed(x),
@@ -80,9 +145,139 @@ impl Instruction {
             [0xf, x, 3, 3] => StoreBinaryCoded(x),
             [0xf, x, 5, 5] => StoreRegisters(x),
             [0xf, x, 6, 5] => LoadRegisters(x),
+            [0xf, 0, 0, 2] => LoadAudioPattern,
+            [0xf, x, 3, 0xa] => SetPitch(x),
             _ => return None,
         })
     }
+
+    /// Opcode of the instruction, [Instruction::decode] gives it back
+    ///
+    /// Operands are cut to the bits of their nibbles
+    pub fn encode(&self) -> u16 {
+        use Instruction::*;
+        let xy = |op: u16, x: Register, y: Register, n: u16| {
+            op << 12 | (x as u16 & 0xf) << 8 | (y as u16 & 0xf) << 4 | n
+        };
+        let xnn = |op: u16, x: Register, value: u8| op << 12 | (x as u16 & 0xf) << 8 | value as u16;
+        let nnn = |op: u16, addr: Address| op << 12 | (addr & 0xfff) as u16;
+        let fx = |x: Register, low: u16| 0xf000 | (x as u16 & 0xf) << 8 | low;
+        match *self {
+            ClearScreen => 0x00e0,
+            ReturnFromSubroutine => 0x00ee,
+            JumpToMachineCode(addr) => nnn(0, addr),
+            JumpTo(addr) => nnn(1, addr),
+            ExecuteSubroutine(addr) => nnn(2, addr),
+            SkipEqTo(x, value) => xnn(3, x, value),
+            SkipNeqTo(x, value) => xnn(4, x, value),
+            SkipEq(x, y) => xy(5, x, y, 0),
+            StoreValue(x, value) => xnn(6, x, value),
+            AddValue(x, value) => xnn(7, x, value),
+            StoreRegister(x, y) => xy(8, x, y, 0),
+            Or(x, y) => xy(8, x, y, 1),
+            And(x, y) => xy(8, x, y, 2),
+            Xor(x, y) => xy(8, x, y, 3),
+            AddRegister(x, y) => xy(8, x, y, 4),
+            SubRegister(x, y) => xy(8, x, y, 5),
+            ShiftRight(x, y) => xy(8, x, y, 6),
+            SubRegisterReverse(x, y) => xy(8, x, y, 7),
+            ShiftLeft(x, y) => xy(8, x, y, 0xe),
+            SkipNeq(x, y) => xy(9, x, y, 0),
+            StoreAddr(addr) => nnn(0xa, addr),
+            JumpToOffset(addr) => nnn(0xb, addr),
+            StoreRandom(x, mask) => xnn(0xc, x, mask),
+            DrawSprite(x, y, count) => xy(0xd, x, y, count as u16 & 0xf),
+            SkipIfKeyPressed(x) => xnn(0xe, x, 0x9e),
+            SkipIfKeyNotPressed(x) => xnn(0xe, x, 0xa1),
+            StoreDelayTimer(x) => fx(x, 0x07),
+            WaitForKeypress(x) => fx(x, 0x0a),
+            SetDelayTimer(x) => fx(x, 0x15),
+            SetSoundTimer(x) => fx(x, 0x18),
+            AddToI(x) => fx(x, 0x1e),
+            StoreDigitLocation(x) => fx(x, 0x29),
+            StoreBinaryCoded(x) => fx(x, 0x33),
+            StoreRegisters(x) => fx(x, 0x55),
+            LoadRegisters(x) => fx(x, 0x65),
+            LoadAudioPattern => 0xf002,
+            SetPitch(x) => fx(x, 0x3a),
+            CycleBackground => 0x02a0,
+            SetZoneColour(x, y) => xy(0xb, x, y, 0),
+            SetLineColour(x, y, count) => xy(0xb, x, y, count as u16 & 0xf),
+            SkipIfKey2Pressed(x) => xnn(0xe, x, 0xf2),
+            SkipIfKey2NotPressed(x) => xnn(0xe, x, 0xf5),
+            DisableMegaChip => 0x0010,
+            EnableMegaChip => 0x0011,
+            StoreLongAddr(high) => xnn(0, 1, high),
+            LoadPalette(count) => xnn(0, 2, count),
+            SetSpriteWidth(width) => xnn(0, 3, width),
+            SetSpriteHeight(height) => xnn(0, 4, height),
+            SetScreenAlpha(alpha) => xnn(0, 5, alpha),
+            PlaySample(looping) => 0x0600 | u16::from(!looping),
+            StopSample => 0x0700,
+            SetBlend(blend) => 0x0800 | blend.code() as u16,
+            SetCollisionColour(index) => xnn(0, 9, index),
+        }
+    }
+
+    /// Opcode pattern of the instruction, like `8XY4`
+    pub fn pattern(&self) -> &'static str {
+        use Instruction::*;
+        match self {
+            ClearScreen => "00E0",
+            ReturnFromSubroutine => "00EE",
+            JumpToMachineCode(_) => "0NNN",
+            JumpTo(_) => "1NNN",
+            ExecuteSubroutine(_) => "2NNN",
+            SkipEqTo(..) => "3XNN",
+            SkipNeqTo(..) => "4XNN",
+            SkipEq(..) => "5XY0",
+            StoreValue(..) => "6XNN",
+            AddValue(..) => "7XNN",
+            StoreRegister(..) => "8XY0",
+            Or(..) => "8XY1",
+            And(..) => "8XY2",
+            Xor(..) => "8XY3",
+            AddRegister(..) => "8XY4",
+            SubRegister(..) => "8XY5",
+            ShiftRight(..) => "8XY6",
+            SubRegisterReverse(..) => "8XY7",
+            ShiftLeft(..) => "8XYE",
+            SkipNeq(..) => "9XY0",
+            StoreAddr(_) => "ANNN",
+            JumpToOffset(_) => "BNNN",
+            StoreRandom(..) => "CXNN",
+            DrawSprite(..) => "DXYN",
+            SkipIfKeyPressed(_) => "EX9E",
+            SkipIfKeyNotPressed(_) => "EXA1",
+            StoreDelayTimer(_) => "FX07",
+            WaitForKeypress(_) => "FX0A",
+            SetDelayTimer(_) => "FX15",
+            SetSoundTimer(_) => "FX18",
+            AddToI(_) => "FX1E",
+            StoreDigitLocation(_) => "FX29",
+            StoreBinaryCoded(_) => "FX33",
+            StoreRegisters(_) => "FX55",
+            LoadRegisters(_) => "FX65",
+            LoadAudioPattern => "F002",
+            SetPitch(_) => "FX3A",
+            CycleBackground => "02A0",
+            SetZoneColour(..) => "BXY0",
+            SetLineColour(..) => "BXYN",
+            SkipIfKey2Pressed(_) => "EXF2",
+            SkipIfKey2NotPressed(_) => "EXF5",
+            DisableMegaChip => "0010",
+            EnableMegaChip => "0011",
+            StoreLongAddr(_) => "01NN",
+            LoadPalette(_) => "02NN",
+            SetSpriteWidth(_) => "03NN",
+            SetSpriteHeight(_) => "04NN",
+            SetScreenAlpha(_) => "05NN",
+            PlaySample(_) => "060N",
+            StopSample => "0700",
+            SetBlend(_) => "080N",
+            SetCollisionColour(_) => "09NN",
+        }
+    }
 }
 
 impl fmt::Display for Instruction {
@@ -124,23 +319,52 @@ impl fmt::Display for Instruction {
             StoreBinaryCoded(x) => write!(f, "binary_encode v{x:x}"),
             StoreRegisters(x) => write!(f, "store_registers v0 .. v{x:x}"),
             LoadRegisters(x) => write!(f, "load_registers v0 .. v{x:x}"),
+            LoadAudioPattern => write!(f, "audio"),
+            SetPitch(x) => write!(f, "pitch := v{x:x}"),
+            CycleBackground => write!(f, "cycle_background"),
+            SetZoneColour(x, y) => write!(f, "zone_colour v{x:x}, v{y:x}"),
+            SetLineColour(x, y, count) => write!(f, "line_colour v{x:x}, v{y:x}, {count}"),
+            SkipIfKey2Pressed(x) => write!(f, "skip_if_pressed_2 v{x:x}"),
+            SkipIfKey2NotPressed(x) => write!(f, "skip_if_not_pressed_2 v{x:x}"),
+            DisableMegaChip => write!(f, "megachip_off"),
+            EnableMegaChip => write!(f, "megachip_on"),
+            StoreLongAddr(high) => write!(f, "i := long {high:#04x}...."),
+            LoadPalette(count) => write!(f, "load_palette {count}"),
+            SetSpriteWidth(width) => write!(f, "sprite_width {width}"),
+            SetSpriteHeight(height) => write!(f, "sprite_height {height}"),
+            SetScreenAlpha(alpha) => write!(f, "screen_alpha {alpha}"),
+            PlaySample(true) => write!(f, "play_sample loop"),
+            PlaySample(false) => write!(f, "play_sample once"),
+            StopSample => write!(f, "stop_sample"),
+            SetBlend(blend) => write!(f, "blend {}", blend.code()),
+            SetCollisionColour(index) => write!(f, "collision_colour {index}"),
         }
     }
 }
 
-pub fn dissassemble(bytes: &[u8]) -> Result<String, ()> {
+/// Disassemble `bytes` of a `platform` program, loaded at `origin`, one
+/// instruction per line
+///
+/// Lines give the address, named after `symbols`, the opcode and the
+/// instruction, or the source line it was assembled from. Words that are not
+/// instructions are shown as data.
+pub fn dissassemble(bytes: &[u8], origin: Address, platform: Platform, symbols: &Symbols) -> String {
     use std::fmt::Write;
     let mut result = String::new();
-    for chunk in bytes.chunks_exact(2) {
-        let a = chunk[0] >> 4;
-        let b = chunk[0] & 0xf;
-        let c = chunk[1] >> 4;
-        let d = chunk[1] & 0xf;
-
-        let Some(instruction) = Instruction::decode([a, b, c, d]) else {
-            break;
+    for (index, chunk) in bytes.chunks(2).enumerate() {
+        let addr = origin + index as Address * INSTRUCTION_SIZE;
+        if let Some(label) = symbols.label(addr) {
+            let _ = writeln!(&mut result, "{label}:");
+        }
+        let [a, b] = [chunk[0], chunk.get(1).copied().unwrap_or_default()];
+        let opcode = u16::from_be_bytes([a, b]);
+        let instruction = Instruction::decode([a >> 4, a & 0xf, b >> 4, b & 0xf], platform);
+        let text = match (symbols.line(addr), instruction) {
+            (Some(line), _) => format!("{:<24}; {line}", line.source),
+            (None, Some(instruction)) => instruction.to_string(),
+            (None, None) => format!("data {opcode:#06x}"),
         };
-        writeln!(&mut result, "{instruction}").map_err(|_| ())?;
+        let _ = writeln!(&mut result, "{addr:03X}  {opcode:04X}  {text}");
     }
-    Ok(result)
+    result
 }
@@ -0,0 +1,239 @@
+//! Execution of the program as basic blocks of pre-bound closures, for bulk
+//! headless runs
+//!
+//! A block starts at [Machine::ip_register] and ends with the first
+//! instruction that may not fall through to the next one: jumps, calls,
+//! returns, skips, long I loads, key waits, and the instructions writing to
+//! memory, so a block never runs code it just overwrote. Blocks are cached by
+//! address and dropped once the memory under them is written.
+
+use std::collections::hash_map::{Entry, HashMap};
+use std::ops::Range;
+
+use super::instruction::Instruction;
+use super::{Address, Crash, Machine, Memory, RunFlow, RunResult, TickError, TickResult, INSTRUCTION_SIZE};
+
+/// Instruction with its operands bound
+type Op = Box<dyn Fn(&mut Machine) -> TickResult>;
+
+/// Instructions of the longest block
+const MAX_BLOCK_LEN: usize = 64;
+
+struct Block {
+    /// Address after the last instruction
+    end: Address,
+    ops: Vec<Op>,
+}
+
+/// Block cache of a single [Machine], running it instead of [Machine::run]
+///
+/// Reaches the same state as the interpreter after every instruction count.
+/// Machines with [Machine::observers] are stepped by the interpreter, so
+/// observers see every instruction.
+pub struct Jit {
+    /// Blocks compiled, by start address
+    blocks: HashMap<Address, Block>,
+    /// Block where the last run stopped before its end, and the next instruction in it
+    cursor: Option<(Address, usize)>,
+}
+
+impl Default for Jit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Jit {
+    pub fn new() -> Self {
+        Self {
+            blocks: HashMap::new(),
+            cursor: None,
+        }
+    }
+
+    /// Run [Machine::speed] instructions, like [Machine::run]
+    pub fn run(&mut self, machine: &mut Machine) -> RunResult {
+        let mut remaining = machine.speed as usize;
+        while remaining > 0 {
+            let (ran, flow) = self.run_block(machine, remaining)?;
+            if let RunFlow::Wait = flow {
+                return Ok(RunFlow::Wait);
+            }
+            remaining -= ran;
+        }
+        Ok(RunFlow::Continue)
+    }
+
+    /// Run a single instruction, like [Machine::step]
+    ///
+    /// Runs the next instruction of the block of the previous step, so stepping
+    /// runs the same code as [Jit::run]
+    pub fn step(&mut self, machine: &mut Machine) -> RunResult {
+        self.run_block(machine, 1).map(|(_, flow)| flow)
+    }
+
+    /// Run the block at [Machine::ip_register], `limit` instructions at most
+    ///
+    /// Resumes the block the last run stopped in when IP is still in it.
+    /// Returns the number of instructions run
+    fn run_block(&mut self, machine: &mut Machine, limit: usize) -> Result<(usize, RunFlow), Box<Crash>> {
+        if !machine.observers.is_empty() {
+            return Ok((1, machine.step()?));
+        }
+        if let Some(written) = machine.memory.take_written() {
+            self.invalidate(written, machine.memory.end());
+        }
+
+        let ip = machine.ip_register;
+        let (start, index) = match self.cursor.take() {
+            Some((start, index))
+                if start + index as Address * INSTRUCTION_SIZE == ip && self.blocks.contains_key(&start) =>
+            {
+                (start, index)
+            }
+            _ => (ip, 0),
+        };
+        let block = match self.blocks.entry(start) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            // Fails out of bound, like fetching the instruction
+            Entry::Vacant(entry) => {
+                entry.insert(compile(&mut machine.memory, start).map_err(|error| machine.crash(error))?)
+            }
+        };
+        let mut ran = 0;
+        for op in block.ops[index..].iter().take(limit) {
+            let flow = op(machine).map_err(|error| machine.crash(error))?;
+            ran += 1;
+            if let RunFlow::Wait = machine.follow(flow) {
+                return Ok((ran, RunFlow::Wait));
+            }
+        }
+        if index + ran < block.ops.len() {
+            self.cursor = Some((start, index + ran));
+        }
+        Ok((ran, RunFlow::Continue))
+    }
+
+    /// Drop the blocks overlapping `written`, in a memory ending at `end`
+    fn invalidate(&mut self, written: Range<Address>, end: Address) {
+        self.blocks.retain(|&start, block| {
+            let overlaps = start < written.end && block.end > written.start;
+            // The instruction at the last byte ends at the first one
+            let wraps = written.start == 0 && block.end > end;
+            !overlaps && !wraps
+        });
+    }
+}
+
+/// Translate the instructions from `start` to the end of their block
+///
+/// Fails like [Machine::tick] when the first instruction cannot be decoded,
+/// later ones end the block before them instead
+fn compile(memory: &mut Memory, start: Address) -> Result<Block, TickError> {
+    let first = memory
+        .fetch(start)?
+        .ok_or_else(|| TickError::unknown_at(memory, start))?;
+    let mut ops = vec![bind(first)];
+    let mut end = start + INSTRUCTION_SIZE;
+    let mut last = first;
+    while !ends_block(last) && ops.len() < MAX_BLOCK_LEN && end < memory.end() - 1 {
+        match memory.fetch(end) {
+            Ok(Some(instruction)) => {
+                ops.push(bind(instruction));
+                end += INSTRUCTION_SIZE;
+                last = instruction;
+            }
+            _ => break,
+        }
+    }
+    log::trace!("compiled block {start:03X}-{end:03X}, {} instructions", ops.len());
+    Ok(Block { end, ops })
+}
+
+/// Whether the instruction may not fall through, or writes to memory
+fn ends_block(instruction: Instruction) -> bool {
+    use Instruction::*;
+    matches!(
+        instruction,
+        ReturnFromSubroutine
+            | JumpToMachineCode(_)
+            | JumpTo(_)
+            | ExecuteSubroutine(_)
+            | SkipEqTo(..)
+            | SkipNeqTo(..)
+            | SkipEq(..)
+            | SkipNeq(..)
+            | JumpToOffset(_)
+            | SkipIfKeyPressed(_)
+            | SkipIfKeyNotPressed(_)
+            | SkipIfKey2Pressed(_)
+            | SkipIfKey2NotPressed(_)
+            | StoreLongAddr(_)
+            | WaitForKeypress(_)
+            | StoreBinaryCoded(_)
+            | StoreRegisters(_)
+    )
+}
+
+fn bind(instruction: Instruction) -> Op {
+    use Instruction::*;
+    match instruction {
+        ClearScreen => Box::new(Machine::clear_screen),
+        ReturnFromSubroutine => Box::new(Machine::return_from_subroutine),
+        JumpToMachineCode(addr) => Box::new(move |m: &mut Machine| m.jump_to_machine_code(addr)),
+        JumpTo(addr) => Box::new(move |m: &mut Machine| m.jump_to(addr)),
+        ExecuteSubroutine(addr) => Box::new(move |m: &mut Machine| m.execute_subroutine(addr)),
+        SkipEqTo(x, value) => Box::new(move |m: &mut Machine| m.skip_eq_to(x, value)),
+        SkipNeqTo(x, value) => Box::new(move |m: &mut Machine| m.skip_neq_to(x, value)),
+        SkipEq(x, y) => Box::new(move |m: &mut Machine| m.skip_eq(x, y)),
+        StoreValue(x, value) => Box::new(move |m: &mut Machine| m.store_value(x, value)),
+        AddValue(x, value) => Box::new(move |m: &mut Machine| m.add_value(x, value)),
+        StoreRegister(x, y) => Box::new(move |m: &mut Machine| m.store_register(x, y)),
+        Or(x, y) => Box::new(move |m: &mut Machine| m.or(x, y)),
+        And(x, y) => Box::new(move |m: &mut Machine| m.and(x, y)),
+        Xor(x, y) => Box::new(move |m: &mut Machine| m.xor(x, y)),
+        AddRegister(x, y) => Box::new(move |m: &mut Machine| m.add_register(x, y)),
+        SubRegister(x, y) => Box::new(move |m: &mut Machine| m.sub_register(x, y)),
+        ShiftRight(x, y) => Box::new(move |m: &mut Machine| m.shift_right(x, y)),
+        SubRegisterReverse(x, y) => Box::new(move |m: &mut Machine| m.sub_register_reverse(x, y)),
+        ShiftLeft(x, y) => Box::new(move |m: &mut Machine| m.shift_left(x, y)),
+        SkipNeq(x, y) => Box::new(move |m: &mut Machine| m.skip_neq(x, y)),
+        StoreAddr(addr) => Box::new(move |m: &mut Machine| m.store_addr(addr)),
+        JumpToOffset(reference) => Box::new(move |m: &mut Machine| m.jump_to_offset(reference)),
+        StoreRandom(x, mask) => Box::new(move |m: &mut Machine| m.store_random(x, mask)),
+        DrawSprite(x, y, line_count) => {
+            Box::new(move |m: &mut Machine| m.draw_sprite(x, y, line_count))
+        }
+        SkipIfKeyPressed(x) => Box::new(move |m: &mut Machine| m.skip_if_key_pressed(x)),
+        SkipIfKeyNotPressed(x) => Box::new(move |m: &mut Machine| m.skip_if_key_not_pressed(x)),
+        StoreDelayTimer(x) => Box::new(move |m: &mut Machine| m.store_delay_timer(x)),
+        WaitForKeypress(x) => Box::new(move |m: &mut Machine| m.wait_for_keypress(x)),
+        SetDelayTimer(x) => Box::new(move |m: &mut Machine| m.set_delay_timer(x)),
+        SetSoundTimer(x) => Box::new(move |m: &mut Machine| m.set_sound_timer(x)),
+        AddToI(x) => Box::new(move |m: &mut Machine| m.add_to_i(x)),
+        StoreDigitLocation(x) => Box::new(move |m: &mut Machine| m.store_digit_location(x)),
+        StoreBinaryCoded(x) => Box::new(move |m: &mut Machine| m.store_binary_coded(x)),
+        StoreRegisters(x) => Box::new(move |m: &mut Machine| m.store_registers(x)),
+        LoadRegisters(x) => Box::new(move |m: &mut Machine| m.load_registers(x)),
+        LoadAudioPattern => Box::new(Machine::load_audio_pattern),
+        SetPitch(x) => Box::new(move |m: &mut Machine| m.set_pitch(x)),
+        CycleBackground => Box::new(Machine::cycle_background),
+        SetZoneColour(x, y) => Box::new(move |m: &mut Machine| m.set_zone_colour(x, y)),
+        SetLineColour(x, y, count) => {
+            Box::new(move |m: &mut Machine| m.set_line_colour(x, y, count))
+        }
+        SkipIfKey2Pressed(x) => Box::new(move |m: &mut Machine| m.skip_if_key_2_pressed(x)),
+        SkipIfKey2NotPressed(x) => Box::new(move |m: &mut Machine| m.skip_if_key_2_not_pressed(x)),
+        DisableMegaChip => Box::new(Machine::disable_megachip),
+        EnableMegaChip => Box::new(Machine::enable_megachip),
+        StoreLongAddr(high) => Box::new(move |m: &mut Machine| m.store_long_addr(high)),
+        LoadPalette(count) => Box::new(move |m: &mut Machine| m.load_palette(count)),
+        SetSpriteWidth(width) => Box::new(move |m: &mut Machine| m.set_sprite_width(width)),
+        SetSpriteHeight(height) => Box::new(move |m: &mut Machine| m.set_sprite_height(height)),
+        SetScreenAlpha(alpha) => Box::new(move |m: &mut Machine| m.set_screen_alpha(alpha)),
+        PlaySample(looping) => Box::new(move |m: &mut Machine| m.play_sample(looping)),
+        StopSample => Box::new(Machine::stop_sample),
+        SetBlend(blend) => Box::new(move |m: &mut Machine| m.set_blend(blend)),
+        SetCollisionColour(index) => Box::new(move |m: &mut Machine| m.set_collision_colour(index)),
+    }
+}
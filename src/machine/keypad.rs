@@ -1,37 +1,62 @@
-/// [Keypad](https://github.com/mattmikolay/chip-8/wiki/CHIP%E2%80%908-Technical-Reference#keypad-input)
-/// 1 2 3 C
-/// 4 5 6 D
-/// 7 8 9 E
-/// A 0 B F
+//! [Keypad](https://github.com/mattmikolay/chip-8/wiki/CHIP%E2%80%908-Technical-Reference#keypad-input)
+//! 1 2 3 C
+//! 4 5 6 D
+//! 7 8 9 E
+//! A 0 B F
+
+use std::collections::VecDeque;
 
 pub type Key = u8;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyEvent {
+    Pressed(Key),
+    Released(Key),
+}
+
+/// Progress of a FX0A wait
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Wait {
+    #[default]
+    Idle,
+    /// Waiting for any key to be pressed
+    Press,
+    /// Waiting for this key to be released
+    Release(Key),
+}
+
 #[derive(Default)]
 pub struct Keypad {
     pressed: [bool; Self::KEY_COUNT],
-    just_pressed: [bool; Self::KEY_COUNT],
-    just_released: [bool; Self::KEY_COUNT],
+    /// Presses and releases that happened since the last [Keypad::wait_for_key],
+    /// only recorded while a wait is in progress
+    events: VecDeque<KeyEvent>,
+    wait: Wait,
 }
 
 impl Keypad {
     pub const KEY_COUNT: usize = 16;
-
-    pub fn reset(&mut self) {
-        self.just_pressed = [false; Self::KEY_COUNT];
-        self.just_released = [false; Self::KEY_COUNT];
-    }
+    /// Keys as they are laid out on the COSMAC VIP, row by row
+    pub const LAYOUT: [[Key; 4]; 4] = [
+        [0x1, 0x2, 0x3, 0xc],
+        [0x4, 0x5, 0x6, 0xd],
+        [0x7, 0x8, 0x9, 0xe],
+        [0xa, 0x0, 0xb, 0xf],
+    ];
 
     pub fn press(&mut self, key: Key) {
         log::debug!("press: {key:X}");
-        if self.pressed[key as usize] == false {
-            self.just_pressed[key as usize] = true;
+        if !self.pressed[key as usize] {
+            self.record(KeyEvent::Pressed(key));
         }
         self.pressed[key as usize] = true;
     }
 
     pub fn release(&mut self, key: Key) {
         log::debug!("release: {key:X}");
-        self.just_released[key as usize] = self.pressed(key);
+        if self.pressed[key as usize] {
+            self.record(KeyEvent::Released(key));
+        }
         self.pressed[key as usize] = false;
     }
 
@@ -42,18 +67,33 @@ impl Keypad {
         }
     }
 
-    pub fn just_pressed(&self) -> Option<Key> {
-        self.just_pressed
-            .iter()
-            .position(|pressed| *pressed)
-            .map(|index| index as Key)
+    fn record(&mut self, event: KeyEvent) {
+        if self.wait != Wait::Idle {
+            self.events.push_back(event);
+        }
     }
 
-    
-    pub fn just_released(&self) -> Option<Key> {
-        self.just_released
-            .iter()
-            .position(|released| *released)
-            .map(|index| index as Key)
+    /// Wait for a key to be pressed then released, as FX0A does on the COSMAC VIP
+    ///
+    /// The first call starts the wait, so keys held or released before it are ignored.
+    /// Every press and release in between calls is queued, so a key tapped
+    /// within a single frame is still caught.
+    pub fn wait_for_key(&mut self) -> Option<Key> {
+        if self.wait == Wait::Idle {
+            self.events.clear();
+            self.wait = Wait::Press;
+        }
+        while let Some(event) = self.events.pop_front() {
+            match (self.wait, event) {
+                (Wait::Press, KeyEvent::Pressed(key)) => self.wait = Wait::Release(key),
+                (Wait::Release(waited), KeyEvent::Released(key)) if key == waited => {
+                    self.wait = Wait::Idle;
+                    self.events.clear();
+                    return Some(key);
+                }
+                _ => {}
+            }
+        }
+        None
     }
 }
@@ -0,0 +1,333 @@
+//! Two machines run side by side on the same program and input, compared after
+//! every instruction, to find where two engines or two quirk presets disagree
+//!
+//! ```text
+//! diverged at step 3, running 204  8016  v0 = v1 >> 1
+//! chip-8
+//!   IP 206  I 000  DT 00  ST 00  stack []
+//!   V 04 08 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+//! schip
+//!   IP 206  I 000  DT 00  ST 00  stack []
+//!   V 01 08 00 00 00 00 00 00 00 00 00 00 00 00 00 01
+//! v0: 04 != 01
+//! vF: 00 != 01
+//! ```
+
+use std::fmt;
+use std::ops::Range;
+
+use thiserror::Error;
+
+use super::instruction::Instruction;
+use super::{
+    Address, AudioPattern, ColourBoard, Crash, Frame, Framebuffer, Key, Machine, Platform, RunFlow, RunResult,
+    Sample,
+};
+#[cfg(feature = "jit")]
+use super::Jit;
+
+/// Memory and screen differences listed at most
+const MAX_DIFFERENCES: usize = 16;
+
+/// How a machine runs its instructions
+pub enum Engine {
+    Interpreter,
+    #[cfg(feature = "jit")]
+    Jit(Box<Jit>),
+}
+
+impl Engine {
+    fn step(&mut self, machine: &mut Machine) -> RunResult {
+        match self {
+            Engine::Interpreter => machine.step(),
+            #[cfg(feature = "jit")]
+            Engine::Jit(jit) => jit.step(machine),
+        }
+    }
+}
+
+/// One of the two machines compared
+pub struct Side {
+    /// Shown in reports, like `interpreter` or `schip quirks`
+    pub name: String,
+    pub machine: Machine,
+    pub engine: Engine,
+}
+
+impl Side {
+    pub fn new(name: impl Into<String>, machine: Machine, engine: Engine) -> Self {
+        Self {
+            name: name.into(),
+            machine,
+            engine,
+        }
+    }
+}
+
+/// State of a machine compared after each step
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub ip_register: Address,
+    pub i_register: Address,
+    pub registers: [u8; 16],
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    /// Return addresses, oldest first
+    pub stack: Vec<Address>,
+    /// Bytes written by the instruction on either machine, the rest of the
+    /// memory was the same before it
+    pub written: Range<Address>,
+    pub memory: Vec<u8>,
+    pub pixels: Vec<bool>,
+    pub colour_board: Option<ColourBoard>,
+    /// Only taken when it changed on either machine, like `memory`
+    pub framebuffer: Option<Framebuffer>,
+    pub audio_pattern: Option<AudioPattern>,
+    pub pitch: u8,
+    pub sample: Option<Sample>,
+}
+
+impl Snapshot {
+    /// Take the state of `machine`, with the bytes of memory in `written`,
+    /// and its framebuffer when `framebuffer_changed`
+    pub fn of(machine: &Machine, written: Range<Address>, framebuffer_changed: bool) -> Self {
+        Self {
+            ip_register: machine.ip_register,
+            i_register: machine.i_register,
+            registers: machine.registers,
+            delay_timer: machine.delay_timer,
+            sound_timer: machine.sound_timer,
+            stack: machine.call_stack.frames().map(Frame::return_address).collect(),
+            memory: machine.memory.range(written.clone()).unwrap_or_default().to_vec(),
+            written,
+            pixels: machine.screen.pixels().to_vec(),
+            colour_board: machine.screen.colour_board().cloned(),
+            framebuffer: framebuffer_changed.then(|| machine.screen.framebuffer().cloned()).flatten(),
+            audio_pattern: machine.audio_pattern,
+            pitch: machine.pitch,
+            sample: machine.sample.clone(),
+        }
+    }
+
+    /// What differs from `other`, one entry per register, byte or pixel
+    pub fn differences(&self, other: &Self) -> Vec<String> {
+        let mut differences = Vec::new();
+        let mut compare = |name: &str, left: String, right: String| {
+            if left != right {
+                differences.push(format!("{name}: {left} != {right}"));
+            }
+        };
+        compare("IP", hex(self.ip_register), hex(other.ip_register));
+        compare("I", hex(self.i_register), hex(other.i_register));
+        for (x, (left, right)) in self.registers.iter().zip(other.registers).enumerate() {
+            compare(&format!("v{x:X}"), format!("{left:02X}"), format!("{right:02X}"));
+        }
+        compare("DT", format!("{:02X}", self.delay_timer), format!("{:02X}", other.delay_timer));
+        compare("ST", format!("{:02X}", self.sound_timer), format!("{:02X}", other.sound_timer));
+        compare("stack", stack(&self.stack), stack(&other.stack));
+        compare("pattern", format!("{:02X?}", self.audio_pattern), format!("{:02X?}", other.audio_pattern));
+        compare("pitch", format!("{:02X}", self.pitch), format!("{:02X}", other.pitch));
+
+        let memory = self.written.clone().zip(self.memory.iter().zip(&other.memory));
+        let bytes = memory.filter(|(_, (left, right))| left != right);
+        for (addr, (left, right)) in bytes.take(MAX_DIFFERENCES) {
+            differences.push(format!("[{addr:03X}]: {left:02X} != {right:02X}"));
+        }
+        let pixels = self.pixels.iter().zip(&other.pixels);
+        let changed = pixels.filter(|(left, right)| left != right).count();
+        if changed > 0 {
+            differences.push(format!("{changed} pixels differ"));
+        }
+        if self.colour_board != other.colour_board {
+            differences.push("colours differ".to_string());
+        }
+        if self.framebuffer != other.framebuffer {
+            differences.push("framebuffers differ".to_string());
+        }
+        if self.sample != other.sample {
+            differences.push("samples differ".to_string());
+        }
+        differences
+    }
+}
+
+fn hex(addr: Address) -> String {
+    format!("{addr:03X}")
+}
+
+fn stack(stack: &[Address]) -> String {
+    let addresses: Vec<_> = stack.iter().map(|addr| hex(*addr)).collect();
+    format!("[{}]", addresses.join(" "))
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "IP {:03X}  I {:03X}  DT {:02X}  ST {:02X}  stack {}\nV",
+            self.ip_register,
+            self.i_register,
+            self.delay_timer,
+            self.sound_timer,
+            stack(&self.stack)
+        )?;
+        for register in self.registers {
+            write!(f, " {register:02X}")?;
+        }
+        Ok(())
+    }
+}
+
+/// First step after which the machines were not in the same state
+#[derive(Debug)]
+pub struct Divergence {
+    /// Steps run by each machine, including the diverging one
+    pub step: u64,
+    /// Address of the instruction that caused it, the same on both machines
+    pub addr: Address,
+    pub opcode: Option<u16>,
+    /// Platform of both machines, to decode [Divergence::opcode]
+    pub platform: Platform,
+    pub names: [String; 2],
+    pub states: [Snapshot; 2],
+    /// Error of each machine on that step, if any
+    pub errors: [Option<String>; 2],
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "diverged at step {}, running {:03X}", self.step, self.addr)?;
+        match self.opcode {
+            Some(opcode) => {
+                let [a, b] = opcode.to_be_bytes();
+                let nibbles = [a >> 4, a & 0xf, b >> 4, b & 0xf];
+                write!(f, "  {opcode:04X}")?;
+                if let Some(instruction) = Instruction::decode(nibbles, self.platform) {
+                    write!(f, "  {instruction}")?;
+                }
+            }
+            None => write!(f, "  ----")?,
+        }
+        for ((name, state), error) in self.names.iter().zip(&self.states).zip(&self.errors) {
+            let state = state.to_string().replace('\n', "\n  ");
+            write!(f, "\n{name}\n  {state}")?;
+            if let Some(error) = error {
+                write!(f, "\n  error: {error}")?;
+            }
+        }
+        for difference in self.states[0].differences(&self.states[1]) {
+            write!(f, "\n{difference}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Diverged(Box<Divergence>),
+    /// Both machines failed the same way, in the same state
+    #[error("both machines failed at step {step}: {error}")]
+    Failed { step: u64, error: Box<Crash> },
+}
+
+/// Two machines run in lock step
+pub struct LockStep {
+    pub left: Side,
+    pub right: Side,
+    steps: u64,
+}
+
+impl LockStep {
+    /// `left` and `right` should have the same platform, program, seed and keys pressed
+    pub fn new(left: Side, right: Side) -> Self {
+        Self {
+            left,
+            right,
+            steps: 0,
+        }
+    }
+
+    /// Steps run so far
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn press(&mut self, key: Key) {
+        self.left.machine.keypad.press(key);
+        self.right.machine.keypad.press(key);
+    }
+
+    pub fn release(&mut self, key: Key) {
+        self.left.machine.keypad.release(key);
+        self.right.machine.keypad.release(key);
+    }
+
+    /// Run an instruction on both machines, then compare them
+    ///
+    /// Only the bytes of memory written and the framebuffers changed by the
+    /// instruction are compared, both machines starting with the same program
+    pub fn step(&mut self) -> Result<RunFlow, Error> {
+        let addr = self.left.machine.ip_register;
+        let opcode = self.left.machine.memory.opcode_at(addr);
+        for machine in [&mut self.left.machine, &mut self.right.machine] {
+            machine.memory.start_step();
+            machine.screen.start_step();
+        }
+        let left = self.left.engine.step(&mut self.left.machine);
+        let right = self.right.engine.step(&mut self.right.machine);
+        self.steps += 1;
+
+        let written = match (self.left.machine.memory.step_written(), self.right.machine.memory.step_written()) {
+            (Some(left), Some(right)) => left.start.min(right.start)..left.end.max(right.end),
+            (Some(written), None) | (None, Some(written)) => written,
+            (None, None) => 0..0,
+        };
+        let framebuffer_changed =
+            self.left.machine.screen.framebuffer_changed() || self.right.machine.screen.framebuffer_changed();
+        let states = [
+            Snapshot::of(&self.left.machine, written.clone(), framebuffer_changed),
+            Snapshot::of(&self.right.machine, written, framebuffer_changed),
+        ];
+        let errors = [
+            left.as_ref().err().map(|crash| crash.error.to_string()),
+            right.as_ref().err().map(|crash| crash.error.to_string()),
+        ];
+        let same_flow = matches!(
+            (&left, &right),
+            (Ok(RunFlow::Continue), Ok(RunFlow::Continue))
+                | (Ok(RunFlow::Wait), Ok(RunFlow::Wait))
+                | (Err(_), Err(_))
+        );
+        if states[0] != states[1] || errors[0] != errors[1] || !same_flow {
+            return Err(Error::Diverged(Box::new(Divergence {
+                step: self.steps,
+                addr,
+                opcode,
+                platform: self.left.machine.platform,
+                names: [self.left.name.clone(), self.right.name.clone()],
+                states,
+                errors,
+            })));
+        }
+        left.map_err(|error| Error::Failed {
+            step: self.steps,
+            error,
+        })
+    }
+
+    /// Count down the timers of both machines, then run the instructions of a
+    /// frame at the speed of [LockStep::left], up to a key wait
+    pub fn run_frame(&mut self) -> Result<(), Error> {
+        for machine in [&mut self.left.machine, &mut self.right.machine] {
+            machine.delay_timer = machine.delay_timer.saturating_sub(1);
+            machine.sound_timer = machine.sound_timer.saturating_sub(1);
+        }
+        for _ in 0..self.left.machine.speed {
+            if let RunFlow::Wait = self.step()? {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+//! 0NNN, calls to machine-code routines of the host computer
+//!
+//! Some ROMs for the COSMAC VIP call routines of the interpreter they were
+//! written for, most famously the screen clear of the two-page hires hack,
+//! or the background cycling of the CHIP-8X interpreter. The few routines
+//! known are emulated natively, the others can be skipped, which is what most
+//! of these ROMs get from modern interpreters.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use super::{Address, Machine, TickResult};
+
+/// What 0NNN does
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum MachineCode {
+    /// 0NNN does nothing
+    Ignore,
+    /// 0NNN fails
+    #[default]
+    Error,
+    /// 0NNN runs the [ROUTINES] at NNN, and fails for other addresses
+    Emulate,
+}
+
+impl MachineCode {
+    pub const ALL: [MachineCode; 3] = [MachineCode::Ignore, MachineCode::Error, MachineCode::Emulate];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MachineCode::Ignore => "ignore",
+            MachineCode::Error => "error",
+            MachineCode::Emulate => "emulate",
+        }
+    }
+}
+
+impl fmt::Display for MachineCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for MachineCode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|mode| mode.name() == s)
+            .ok_or_else(|| format!("unknown machine code mode {s:?}, expected ignore, error or emulate"))
+    }
+}
+
+impl TryFrom<String> for MachineCode {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        s.parse()
+    }
+}
+
+/// Machine-code routine of a VIP interpreter, emulated natively
+pub struct Routine {
+    /// NNN of the 0NNN calling it
+    pub addr: Address,
+    pub name: &'static str,
+    pub run: fn(&mut Machine) -> TickResult,
+}
+
+/// Routines run by 0NNN with [MachineCode::Emulate]
+pub const ROUTINES: &[Routine] = &[
+    Routine {
+        addr: 0x230,
+        name: "two-page hires clear",
+        run: Machine::clear_screen,
+    },
+    // CHIP-8X programs cannot be told apart from CHIP-8 ones, and reach it
+    // when run as CHIP-8, where there is no background to cycle
+    Routine {
+        addr: 0x2a0,
+        name: "CHIP-8X background cycle",
+        run: Machine::cycle_background,
+    },
+];
+
+/// Routine called by 0NNN with NNN = `addr`, if known
+pub fn routine(addr: Address) -> Option<&'static Routine> {
+    ROUTINES.iter().find(|routine| routine.addr == addr)
+}
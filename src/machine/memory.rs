@@ -1,15 +1,143 @@
+use std::borrow::Cow;
+use std::fmt;
 use std::ops::Range;
+use std::str::FromStr;
+
+use serde::Deserialize;
 use thiserror::Error;
 
-pub type Address = u16;
+use super::instruction::Instruction;
+use super::Platform;
+
+/// 24 bits are used, for the long I of the MegaChip
+pub type Address = u32;
+
+pub struct Memory {
+    bytes: Box<[u8]>,
+    /// Instruction decoded at each address of the first [Memory::CACHED_SIZE]
+    /// bytes, see [Memory::instruction_at]
+    decoded: Box<[Decoded]>,
+    /// Bytes written since the last [Memory::take_written]
+    written: Option<Range<Address>>,
+    /// Bytes written since the last [Memory::start_step], all of them at first
+    step_written: Option<Range<Address>>,
+    /// Checks of the accesses made by instructions
+    pub policy: Policy,
+    /// Program loaded, protected with [Policy::protect_program]
+    program: Range<Address>,
+    /// Instructions are decoded for it
+    platform: Platform,
+}
+
+/// What accesses by instructions past the end of memory do
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum Overflow {
+    /// Addresses wrap around the end of memory, cut to 12 bits like on the
+    /// COSMAC VIP
+    Wrap,
+    /// The access fails
+    #[default]
+    Error,
+    /// The access fails, telling what kind of access it was and its size
+    ErrorWithContext,
+}
+
+impl Overflow {
+    pub const ALL: [Overflow; 3] = [Overflow::Wrap, Overflow::Error, Overflow::ErrorWithContext];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Overflow::Wrap => "wrap",
+            Overflow::Error => "error",
+            Overflow::ErrorWithContext => "error-with-context",
+        }
+    }
+}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL.into_iter().find(|overflow| overflow.name() == s).ok_or_else(|| {
+            format!("unknown memory overflow {s:?}, expected wrap, error or error-with-context")
+        })
+    }
+}
 
-pub struct Memory([u8; Self::SIZE]);
+impl TryFrom<String> for Overflow {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        s.parse()
+    }
+}
+
+/// How the accesses of instructions are checked
+///
+/// Only [Memory::fetch], [Memory::read], [Memory::read_span],
+/// [Memory::write] and [Memory::write_span] follow it, other accessors are
+/// for the emulator itself
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Policy {
+    pub overflow: Overflow,
+    /// Writes below [Memory::PROGRAM_ENTRYPOINT], where the font lives, fail
+    pub protect_font: bool,
+    /// Writes over the program loaded fail, catching ROMs overwriting their code
+    pub protect_program: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Fetch,
+    Read,
+    Write,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Access::Fetch => "fetch",
+            Access::Read => "read",
+            Access::Write => "write",
+        })
+    }
+}
+
+/// Part of the memory protected by a [Policy]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Font,
+    Program,
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Region::Font => "font",
+            Region::Program => "program",
+        })
+    }
+}
+
+/// Entry of the decoded instruction cache
+#[derive(Debug, Clone, Copy)]
+enum Decoded {
+    /// Not decoded since the address was last written
+    Stale,
+    Invalid,
+    Valid(Instruction),
+}
 
 impl Default for Memory {
     fn default() -> Self {
-        let mut memory = Self::zeroed();
-        memory.load_font(&DEFAULT_FONT);
-        memory
+        Self::new(Self::SIZE)
     }
 }
 
@@ -19,6 +147,15 @@ pub enum Error {
     OutOfBound(Address),
     #[error("out of bound access at {0:?}")]
     RangeOutOfBound(Range<Address>),
+    #[error("{access} of {len} bytes at {addr:#05x} goes past the end of memory at {end:#05x}")]
+    AccessOutOfBound {
+        access: Access,
+        addr: Address,
+        len: Address,
+        end: Address,
+    },
+    #[error("write at {addr:#05x} into the protected {region}")]
+    Protected { addr: Address, region: Region },
 }
 
 pub const GLYPH_SIZE: Address = 5;
@@ -45,28 +182,79 @@ const DEFAULT_FONT: Font = [
 ];
 
 impl Memory {
+    /// Bytes of memory of every platform but the XO-CHIP and the MegaChip, see [Platform::memory_size]
     pub const SIZE: usize = 4096;
     pub const FONT_LOCATION: Address = 0x0;
     pub const PROGRAM_ENTRYPOINT: Address = 0x200;
     pub const MEMORY_END: Address = Self::SIZE as Address;
+    /// Bytes at the start of memory whose instructions are cached, the whole
+    /// memory of the XO-CHIP, see [Platform::memory_size]
+    ///
+    /// Jumps only reach the first 4 KiB, the MegaChip keeps its graphics and
+    /// samples above, so instructions past it are decoded every time
+    pub const CACHED_SIZE: usize = 0x10000;
 
     pub const FONT_RANGE: Range<Address> = 0..Self::PROGRAM_ENTRYPOINT;
+    /// Bytes taken by the font, from [Memory::FONT_LOCATION]
+    pub const FONT_SIZE: Address = std::mem::size_of::<Font>() as Address;
     pub const PROGRAM_RANGE: Range<Address> = Self::PROGRAM_ENTRYPOINT..Self::MEMORY_END;
 
-    pub fn zeroed() -> Self {
-        Self([0; Self::SIZE])
+    /// `size` bytes of memory, with the font loaded
+    pub fn new(size: usize) -> Self {
+        let mut memory = Self::zeroed(size);
+        memory.load_font(&DEFAULT_FONT);
+        memory
+    }
+
+    pub fn zeroed(size: usize) -> Self {
+        Self {
+            bytes: vec![0; size].into_boxed_slice(),
+            decoded: vec![Decoded::Stale; size.min(Self::CACHED_SIZE)].into_boxed_slice(),
+            written: None,
+            step_written: Some(0..size as Address),
+            policy: Policy::default(),
+            program: Self::PROGRAM_ENTRYPOINT..Self::PROGRAM_ENTRYPOINT,
+            platform: Platform::default(),
+        }
+    }
+
+    /// Decode instructions for `platform` from now on
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.platform = platform;
+        self.invalidate(0..self.end());
+    }
+
+    /// Bytes of memory
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Address after the last byte of memory
+    pub fn end(&self) -> Address {
+        self.bytes.len() as Address
     }
 
     pub fn load_program(&mut self, program: &[u8]) -> Result<(), Error> {
-        let program_memory = &mut self.range_mut(Self::PROGRAM_RANGE)?;
+        self.load_program_at(program, Self::PROGRAM_ENTRYPOINT)
+    }
+
+    pub fn load_program_at(&mut self, program: &[u8], start: Address) -> Result<(), Error> {
+        let end = self.end();
+        let program_memory = &mut self.range_mut(start..end)?;
         if program_memory.len() < program.len() {
             // TODO(Mehdi): Change :eyes:
-            return Err(Error::OutOfBound(Self::MEMORY_END));
+            return Err(Error::OutOfBound(end));
         }
         program_memory[..program.len()].copy_from_slice(program);
+        self.program = start..start + program.len() as Address;
         Ok(())
     }
 
+    /// Addresses of the program loaded
+    pub fn program(&self) -> Range<Address> {
+        self.program.clone()
+    }
+
     pub fn load_font(&mut self, font: &Font) {
         let glyph_locations = self
             .range_mut(Self::FONT_RANGE)
@@ -79,34 +267,195 @@ impl Memory {
     }
 
     pub fn get(&self, addr: Address) -> Result<u8, Error> {
-        self.0
+        self.bytes
             .get(addr as usize)
             .copied()
             .ok_or(Error::OutOfBound(addr))
     }
 
+    /// Invalidates the instructions decoded over `addr`
     pub fn get_mut(&mut self, addr: Address) -> Result<&mut u8, Error> {
-        self.0.get_mut(addr as usize).ok_or(Error::OutOfBound(addr))
+        self.invalidate(addr..addr.saturating_add(1));
+        self.bytes.get_mut(addr as usize).ok_or(Error::OutOfBound(addr))
     }
 
     pub fn range(&self, range: Range<Address>) -> Result<&[u8], Error> {
-        self.0
+        self.bytes
             .get(range.start as usize..range.end as usize)
             .ok_or(Error::RangeOutOfBound(range))
     }
 
+    /// Invalidates the instructions decoded over `range`
     pub fn range_mut(&mut self, range: Range<Address>) -> Result<&mut [u8], Error> {
-        self.0
+        self.invalidate(range.clone());
+        self.bytes
             .get_mut(range.start as usize..range.end as usize)
             .ok_or(Error::RangeOutOfBound(range))
     }
 
     /// Get 4 nibbles at an address
-    /// # Panic
-    /// Panics `addr` or `addr + 1` are out of [Machine::memory] range
     pub fn nibbles_at(&self, addr: Address) -> Result<[u8; 4], Error> {
         let a = self.get(addr)?;
-        let b = self.get(addr + 1)?;
+        let b = self.get(addr.wrapping_add(1))?;
         Ok([a >> 4, a & 0xf, b >> 4, b & 0xf])
     }
+
+    /// Raw opcode at `addr`, ending at the first byte like [Memory::instruction_at]
+    pub fn opcode_at(&self, addr: Address) -> Option<u16> {
+        let a = *self.bytes.get(addr as usize)?;
+        let b = self.bytes[(addr as usize + 1) % self.size()];
+        Some(u16::from_be_bytes([a, b]))
+    }
+
+    /// `addr` as accessed by instructions: wrapped around the end of memory
+    /// with [Overflow::Wrap]
+    pub fn wrap(&self, addr: Address) -> Address {
+        match self.policy.overflow {
+            Overflow::Wrap => addr % self.end(),
+            Overflow::Error | Overflow::ErrorWithContext => addr,
+        }
+    }
+
+    /// Start of the `len` bytes accessed at `addr`, following [Policy::overflow]
+    fn resolve(&self, access: Access, addr: Address, len: Address) -> Result<usize, Error> {
+        if addr as usize + len as usize <= self.size() {
+            return Ok(addr as usize);
+        }
+        match self.policy.overflow {
+            Overflow::Wrap => Ok(addr as usize % self.size()),
+            Overflow::Error if len == 1 => Err(Error::OutOfBound(addr)),
+            Overflow::Error => Err(Error::RangeOutOfBound(addr..addr.saturating_add(len))),
+            Overflow::ErrorWithContext => Err(Error::AccessOutOfBound {
+                access,
+                addr,
+                len,
+                end: self.end(),
+            }),
+        }
+    }
+
+    /// Byte read by an instruction
+    pub fn read(&self, addr: Address) -> Result<u8, Error> {
+        let start = self.resolve(Access::Read, addr, 1)?;
+        Ok(self.bytes[start])
+    }
+
+    /// Bytes read by an instruction, wrapping around the end of memory with [Overflow::Wrap]
+    pub fn read_span(&self, addr: Address, len: Address) -> Result<Cow<'_, [u8]>, Error> {
+        let start = self.resolve(Access::Read, addr, len)?;
+        Ok(self.span(start, len))
+    }
+
+    fn span(&self, start: usize, len: Address) -> Cow<'_, [u8]> {
+        match self.bytes.get(start..start + len as usize) {
+            Some(bytes) => Cow::Borrowed(bytes),
+            None => (0..len as usize)
+                .map(|offset| self.bytes[(start + offset) % self.size()])
+                .collect(),
+        }
+    }
+
+    /// Byte written by an instruction
+    pub fn write(&mut self, addr: Address, value: u8) -> Result<(), Error> {
+        self.write_span(addr, &[value])
+    }
+
+    /// Bytes written by an instruction, following [Policy]
+    ///
+    /// Nothing is written when any byte cannot be
+    pub fn write_span(&mut self, addr: Address, values: &[u8]) -> Result<(), Error> {
+        let len = values.len() as Address;
+        let start = self.resolve(Access::Write, addr, len)?;
+        let size = self.size();
+        let addresses = (start..start + values.len()).map(|addr| (addr % size) as Address);
+        for addr in addresses.clone() {
+            if let Some(region) = self.protected(addr) {
+                return Err(Error::Protected { addr, region });
+            }
+        }
+        for (addr, value) in addresses.zip(values) {
+            *self.get_mut(addr)? = *value;
+        }
+        Ok(())
+    }
+
+    fn protected(&self, addr: Address) -> Option<Region> {
+        if self.policy.protect_font && Self::FONT_RANGE.contains(&addr) {
+            Some(Region::Font)
+        } else if self.policy.protect_program
+            && self.program.contains(&addr)
+        {
+            Some(Region::Program)
+        } else {
+            None
+        }
+    }
+
+    /// Instruction fetched at `addr`, following [Policy::overflow]
+    pub fn fetch(&mut self, addr: Address) -> Result<Option<Instruction>, Error> {
+        let start = self.resolve(Access::Fetch, addr, 2)?;
+        self.instruction_at(start as Address)
+    }
+
+    /// Instruction at `addr`, decoded once until the memory under it is written
+    ///
+    /// The instruction at the last byte ends at the first one
+    pub fn instruction_at(&mut self, addr: Address) -> Result<Option<Instruction>, Error> {
+        match self.decoded.get(addr as usize) {
+            Some(Decoded::Valid(instruction)) => return Ok(Some(*instruction)),
+            Some(Decoded::Invalid) => return Ok(None),
+            Some(Decoded::Stale) => {}
+            None if (addr as usize) < self.size() => {}
+            None => return Err(Error::OutOfBound(addr)),
+        }
+        let [a, b] = [self.bytes[addr as usize], self.bytes[(addr as usize + 1) % self.size()]];
+        let instruction = Instruction::decode([a >> 4, a & 0xf, b >> 4, b & 0xf], self.platform);
+        if let Some(decoded) = self.decoded.get_mut(addr as usize) {
+            *decoded = match instruction {
+                Some(instruction) => Decoded::Valid(instruction),
+                None => Decoded::Invalid,
+            };
+        }
+        Ok(instruction)
+    }
+
+    /// Range covering every byte written since the last call, if any
+    pub fn take_written(&mut self) -> Option<Range<Address>> {
+        self.written.take()
+    }
+
+    /// Track the bytes written from now on, until the next call, see [Memory::step_written]
+    pub fn start_step(&mut self) {
+        self.step_written = None;
+    }
+
+    /// Range covering every byte written since the last [Memory::start_step]
+    ///
+    /// Called right after [Machine::step](super::Machine::step), only the bytes
+    /// the instruction wrote, so observers do not have to go through the whole memory
+    pub fn step_written(&self) -> Option<Range<Address>> {
+        self.step_written.clone()
+    }
+
+    /// Forget the instructions decoded over `range`, including the one starting
+    /// the byte before
+    fn invalidate(&mut self, range: Range<Address>) {
+        let cover = |written: Option<Range<Address>>| match written {
+            Some(written) => written.start.min(range.start)..written.end.max(range.end),
+            None => range.clone(),
+        };
+        self.written = Some(cover(self.written.take()));
+        self.step_written = Some(cover(self.step_written.take()));
+        let start = range.start.saturating_sub(1) as usize;
+        let end = (range.end as usize).min(self.decoded.len());
+        if let Some(decoded) = self.decoded.get_mut(start..end) {
+            decoded.fill(Decoded::Stale);
+        }
+        // The instruction at the last byte ends at the first one
+        if range.start == 0 {
+            if let Some(last) = self.decoded.get_mut(self.bytes.len() - 1) {
+                *last = Decoded::Stale;
+            }
+        }
+    }
 }
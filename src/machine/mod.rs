@@ -1,19 +1,43 @@
 mod call_stack;
+mod crash;
 mod execute;
+#[cfg(feature = "jit")]
+mod jit;
 mod keypad;
+pub mod machine_code;
+pub mod lockstep;
 mod memory;
+mod observer;
+mod platform;
+mod quirks;
+mod sample;
 mod screen;
+mod symbols;
 pub mod instruction;
 
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use thiserror::Error;
 
-use call_stack::CallStack;
+use call_stack::Depth;
+pub use call_stack::{CallStack, Depth as StackDepth, Frame, Policy as CallStackPolicy};
+pub use crash::Crash;
 pub use keypad::{Key, Keypad};
-pub use memory::{Address, Memory};
-pub use screen::Screen;
+pub use machine_code::MachineCode;
+#[cfg(feature = "jit")]
+pub use jit::Jit;
+pub use memory::{Access, Address, Error as MemoryError, Memory, Overflow, Policy as MemoryPolicy, Region};
+pub use observer::Observer;
+pub use platform::Platform;
+pub use quirks::Quirks;
+pub use sample::{Error as SampleError, Sample};
+pub use screen::{Blend, ColourBoard, Framebuffer, Palette, Rgb, Screen};
+pub use symbols::Symbols;
 use instruction::Instruction;
 
 pub struct Machine {
+    /// Variant emulated, see [Machine::for_platform]
+    pub platform: Platform,
     pub registers: [u8; 16],
     pub i_register: Address,
     pub ip_register: Address,
@@ -23,6 +47,25 @@ pub struct Machine {
     pub call_stack: CallStack,
     pub screen: Screen,
     pub keypad: Keypad,
+    /// CHIP-8X second keypad, read by EXF2 and EXF5
+    pub keypad_2: Keypad,
+    /// XO-CHIP 1-bit audio pattern, played instead of the beep once loaded
+    pub audio_pattern: Option<AudioPattern>,
+    /// XO-CHIP audio pattern playback pitch
+    pub pitch: u8,
+    /// MegaChip sound playing, from 060N to 0700
+    pub sample: Option<Sample>,
+    pub quirks: Quirks,
+    /// What 0NNN does
+    pub machine_code: MachineCode,
+    /// Instructions run per frame by [Machine::run]
+    pub speed: u32,
+    /// Notified of every [Machine::step]
+    pub observers: Vec<Box<dyn Observer>>,
+    /// Labels and source lines of the program, when known
+    pub symbols: Symbols,
+    /// Source of CXNN, see [Machine::seed]
+    pub rng: StdRng,
 }
 
 pub type TickResult = Result<TickFlow, TickError>;
@@ -40,13 +83,25 @@ pub enum TickError {
     StackError(#[from] call_stack::Error),
     #[error(transparent)]
     MemoryError(#[from] memory::Error),
+    #[error(transparent)]
+    SampleError(#[from] sample::Error),
     #[error("unimplemented instruction: {0}")]
     Unimplemented(String),
-    #[error("unknown instruction")]
-    Unknown,
+    #[error("unknown instruction {0:#06x}")]
+    Unknown(u16),
+}
+
+impl TickError {
+    /// Instruction at `addr` cannot be decoded, or is out of memory
+    fn unknown_at(memory: &Memory, addr: Address) -> Self {
+        match memory.opcode_at(addr) {
+            Some(opcode) => TickError::Unknown(opcode),
+            None => memory::Error::OutOfBound(addr).into(),
+        }
+    }
 }
 
-pub type RunResult = Result<RunFlow, TickError>;
+pub type RunResult = Result<RunFlow, Box<Crash>>;
 
 pub enum RunFlow {
     Continue,
@@ -55,6 +110,8 @@ pub enum RunFlow {
 
 pub type Register = u8;
 
+pub type AudioPattern = [u8; 16];
+
 pub const INSTRUCTION_SIZE: Address = 2;
 
 pub fn u8_from_nibbles(a: u8, b: u8) -> u8 {
@@ -65,27 +122,75 @@ pub fn u16_from_nibbles(a: u8, b: u8, c: u8) -> u16 {
     (a as u16) << 8 | (b as u16) << 4 | (c as u16)
 }
 
+impl Default for Machine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Machine {
+    pub const DEFAULT_SPEED: u32 = 60;
+
     pub fn new() -> Self {
+        Self::for_platform(Platform::default())
+    }
+
+    /// Machine with the entrypoint, screen and instructions of `platform`
+    ///
+    /// Quirks, speed and other settings are left to their defaults, see
+    /// [Platform::quirks] for those programs usually expect
+    pub fn for_platform(platform: Platform) -> Self {
+        let mut memory = Memory::new(platform.memory_size());
+        memory.set_platform(platform);
+        let (width, height) = platform.screen_size();
+        let mut screen = Screen::new(width, height);
+        match platform {
+            Platform::Chip8X => screen.add_colour_board(),
+            Platform::MegaChip => screen.add_framebuffer(),
+            _ => {}
+        }
         Machine {
+            platform,
             registers: [0; 16],
             i_register: 0,
-            ip_register: Memory::PROGRAM_ENTRYPOINT,
-            memory: Memory::default(),
+            ip_register: platform.entrypoint(),
+            memory,
             delay_timer: 0,
             sound_timer: 0,
             call_stack: CallStack::new(),
-            screen: Screen::default(),
+            screen,
             keypad: Keypad::default(),
+            keypad_2: Keypad::default(),
+            audio_pattern: None,
+            pitch: 64,
+            sample: None,
+            quirks: Quirks::default(),
+            machine_code: MachineCode::default(),
+            speed: Self::DEFAULT_SPEED,
+            observers: Vec::new(),
+            symbols: Symbols::default(),
+            rng: StdRng::from_entropy(),
         }
     }
 
+    /// Make CXNN draw the same numbers on every run with the same `seed`
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Load `program` at the [Platform::load_address] of the machine
     pub fn load_program(&mut self, program: &[u8]) -> Result<(), memory::Error> {
-        self.memory.load_program(program)
+        self.memory.load_program_at(program, self.platform.load_address())?;
+        let mut observers = std::mem::take(&mut self.observers);
+        for observer in &mut observers {
+            observer.program_loaded(self);
+        }
+        self.observers = observers;
+        Ok(())
     }
 
     pub fn run(&mut self) -> RunResult {
-        for _ in 0..60 {
+        for _ in 0..self.speed {
             if let RunFlow::Wait = self.step()? {
                 return Ok(RunFlow::Wait)
             }
@@ -94,13 +199,48 @@ impl Machine {
     }
 
     pub fn step(&mut self) -> RunResult {
-        match self.tick()? {
-            TickFlow::Advance => self.ip_register += INSTRUCTION_SIZE,
-            TickFlow::Skip => self.ip_register += INSTRUCTION_SIZE * 2,
-            TickFlow::GoTo(addr) => self.ip_register = addr,
-            TickFlow::Wait => return Ok(RunFlow::Wait),
+        if self.observers.is_empty() {
+            return self.step_unobserved();
         }
-        Ok(RunFlow::Continue)
+        // Taken out so observers can look at the machine
+        let mut observers = std::mem::take(&mut self.observers);
+        for observer in &mut observers {
+            observer.before_step(self);
+        }
+        self.memory.start_step();
+        let result = self.step_unobserved();
+        for observer in &mut observers {
+            observer.after_step(self, &result);
+        }
+        self.observers = observers;
+        result
+    }
+
+    fn step_unobserved(&mut self) -> RunResult {
+        let flow = self.tick().map_err(|error| self.crash(error))?;
+        Ok(self.follow(flow))
+    }
+
+    /// Report of `error`, raised by the instruction at [Machine::ip_register]
+    pub fn crash(&self, error: TickError) -> Box<Crash> {
+        Box::new(Crash::new(self, error))
+    }
+
+    /// Move [Machine::ip_register] to the next instruction after `flow`
+    fn follow(&mut self, flow: TickFlow) -> RunFlow {
+        let next = match flow {
+            TickFlow::Advance => self.ip_register + INSTRUCTION_SIZE,
+            TickFlow::Skip => self.ip_register + INSTRUCTION_SIZE * 2,
+            TickFlow::GoTo(addr) => addr,
+            TickFlow::Wait => return RunFlow::Wait,
+        };
+        self.ip_register = self.memory.wrap(next);
+        RunFlow::Continue
+    }
+
+    /// Rate at which the bits of [Machine::audio_pattern] are played, in Hz
+    pub fn audio_pattern_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
     }
 
     /// Get register X
@@ -119,17 +259,25 @@ impl Machine {
 
     pub fn current_instruction(&self) -> Result<Instruction, TickError> {
         let nibbles = self.memory.nibbles_at(self.ip_register)?;
-        let instruction = Instruction::decode(nibbles).ok_or(TickError::Unknown)?;
+        let instruction = Instruction::decode(nibbles, self.platform)
+            .ok_or_else(|| self.unknown_instruction())?;
         Ok(instruction)
     }
 
     /// Run current instruction without updating [Machine::ip_register]
     pub fn tick(&mut self) -> TickResult {
-        let instruction = self.current_instruction()?;
+        let instruction = self
+            .memory
+            .fetch(self.ip_register)?
+            .ok_or_else(|| self.unknown_instruction())?;
         log::trace!("{instruction}");
         self.execute(instruction)
     }
 
+    fn unknown_instruction(&self) -> TickError {
+        TickError::unknown_at(&self.memory, self.ip_register)
+    }
+
     fn execute(&mut self, instruction: Instruction) -> TickResult {
         match instruction {
             Instruction::ClearScreen => self.clear_screen(),
@@ -167,6 +315,24 @@ impl Machine {
             Instruction::StoreBinaryCoded(x) => self.store_binary_coded(x),
             Instruction::StoreRegisters(x) => self.store_registers(x),
             Instruction::LoadRegisters(x) => self.load_registers(x),
+            Instruction::LoadAudioPattern => self.load_audio_pattern(),
+            Instruction::SetPitch(x) => self.set_pitch(x),
+            Instruction::CycleBackground => self.cycle_background(),
+            Instruction::SetZoneColour(x, y) => self.set_zone_colour(x, y),
+            Instruction::SetLineColour(x, y, count) => self.set_line_colour(x, y, count),
+            Instruction::SkipIfKey2Pressed(x) => self.skip_if_key_2_pressed(x),
+            Instruction::SkipIfKey2NotPressed(x) => self.skip_if_key_2_not_pressed(x),
+            Instruction::DisableMegaChip => self.disable_megachip(),
+            Instruction::EnableMegaChip => self.enable_megachip(),
+            Instruction::StoreLongAddr(high) => self.store_long_addr(high),
+            Instruction::LoadPalette(count) => self.load_palette(count),
+            Instruction::SetSpriteWidth(width) => self.set_sprite_width(width),
+            Instruction::SetSpriteHeight(height) => self.set_sprite_height(height),
+            Instruction::SetScreenAlpha(alpha) => self.set_screen_alpha(alpha),
+            Instruction::PlaySample(looping) => self.play_sample(looping),
+            Instruction::StopSample => self.stop_sample(),
+            Instruction::SetBlend(blend) => self.set_blend(blend),
+            Instruction::SetCollisionColour(index) => self.set_collision_colour(index),
         }
     }
 }
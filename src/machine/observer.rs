@@ -0,0 +1,21 @@
+use super::{Machine, RunResult};
+
+/// Hook on every [Machine::step], to trace or profile execution
+///
+/// Observers only see the machine, they cannot change it
+pub trait Observer {
+    /// Called once [Machine::load_program] loaded a program, observers are
+    /// usually moved to the new machine of each program
+    fn program_loaded(&mut self, _machine: &Machine) {}
+
+    /// Called before the instruction at [Machine::ip_register] is run
+    ///
+    /// [Memory::step_written](super::Memory::step_written) covers the bytes
+    /// written since the previous instruction, like by the memory editor
+    fn before_step(&mut self, _machine: &Machine) {}
+
+    /// Called once the instruction has run, or failed
+    ///
+    /// [Memory::step_written](super::Memory::step_written) covers the bytes it wrote
+    fn after_step(&mut self, _machine: &Machine, _result: &RunResult) {}
+}
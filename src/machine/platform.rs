@@ -0,0 +1,222 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use super::{Address, Depth, Instruction, Machine, MachineCode, Memory, Quirks, INSTRUCTION_SIZE};
+
+/// CHIP-8 variant a program was written for
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum Platform {
+    #[default]
+    Chip8,
+    SuperChip,
+    XoChip,
+    /// COSMAC VIP two-page hires CHIP-8: 64x64 screen, programs start at 0x2C0
+    /// after the interpreter patch loaded with them
+    HiRes,
+    /// COSMAC VIP with the VP-590 colour board, programs start at 0x300
+    Chip8X,
+    /// SUPER-CHIP with a 256x192 colour mode, 24-bit I and digitised sound
+    MegaChip,
+}
+
+impl Platform {
+    pub const ALL: [Platform; 6] = [
+        Platform::Chip8,
+        Platform::SuperChip,
+        Platform::XoChip,
+        Platform::HiRes,
+        Platform::Chip8X,
+        Platform::MegaChip,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip-8",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xo-chip",
+            Platform::HiRes => "hires",
+            Platform::Chip8X => "chip-8x",
+            Platform::MegaChip => "megachip",
+        }
+    }
+
+    /// Bytes of memory, 64 KiB for the 16-bit I of the XO-CHIP and 16 MiB for
+    /// the 24-bit I of the MegaChip
+    pub fn memory_size(self) -> usize {
+        match self {
+            Platform::XoChip => 0x1_0000,
+            Platform::MegaChip => 0x100_0000,
+            _ => Memory::SIZE,
+        }
+    }
+
+    /// Address the program is loaded at
+    pub fn load_address(self) -> Address {
+        match self {
+            Platform::Chip8X => 0x300,
+            _ => Memory::PROGRAM_ENTRYPOINT,
+        }
+    }
+
+    /// Address of the first instruction run
+    pub fn entrypoint(self) -> Address {
+        match self {
+            Platform::HiRes => 0x2c0,
+            _ => self.load_address(),
+        }
+    }
+
+    /// Width and height of the screen, in pixels, outside of the MegaChip mode
+    pub fn screen_size(self) -> (usize, usize) {
+        match self {
+            Platform::HiRes => (64, 64),
+            _ => (64, 32),
+        }
+    }
+
+    /// Quirks programs for this platform usually expect
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Platform::Chip8 | Platform::HiRes | Platform::Chip8X => Quirks::default(),
+            Platform::SuperChip | Platform::MegaChip => Quirks {
+                shift: true,
+                jump: true,
+                ..Quirks::default()
+            },
+            Platform::XoChip => Quirks {
+                memory_increment: true,
+                wrap: true,
+                ..Quirks::default()
+            },
+        }
+    }
+
+    /// Call stack programs for this platform usually expect
+    pub fn stack_depth(self) -> Depth {
+        match self {
+            Platform::Chip8 | Platform::HiRes | Platform::Chip8X => Depth::Vip,
+            Platform::SuperChip | Platform::XoChip | Platform::MegaChip => Depth::Schip,
+        }
+    }
+
+    /// Instructions per frame programs for this platform usually expect
+    pub fn speed(self) -> u32 {
+        match self {
+            Platform::Chip8 | Platform::SuperChip | Platform::HiRes | Platform::Chip8X => {
+                Machine::DEFAULT_SPEED
+            }
+            Platform::XoChip | Platform::MegaChip => 1000,
+        }
+    }
+
+    /// What 0NNN does in programs for this platform, hires programs clear
+    /// their screen with a machine-code routine
+    pub fn machine_code(self) -> MachineCode {
+        match self {
+            Platform::HiRes => MachineCode::Emulate,
+            _ => MachineCode::default(),
+        }
+    }
+
+    /// Guess the platform of a program from the instructions it contains
+    ///
+    /// Only instructions reachable from the entrypoint are considered, so
+    /// sprite data is not mistaken for extension opcodes. Computed jumps
+    /// (BNNN) are not followed. Hires programs are recognized by their first
+    /// instruction jumping over the interpreter patch, to 0x260, followed by a
+    /// reachable call to the hires screen clear, 0230. MegaChip programs are
+    /// recognized by their first instruction turning on the MegaChip mode.
+    /// CHIP-8X programs cannot be told apart and are never guessed.
+    pub fn detect(program: &[u8]) -> Platform {
+        if program.starts_with(&[0x00, 0x11]) {
+            return Platform::MegaChip;
+        }
+        if program.len() > Memory::PROGRAM_RANGE.len() {
+            return Platform::XoChip;
+        }
+        // Plain CHIP-8 programs may jump over 0x60 bytes of data too
+        let hires_patch = program.starts_with(&[0x12, 0x60]);
+        let nibbles_at = |addr: Address| {
+            let offset = addr.checked_sub(Memory::PROGRAM_ENTRYPOINT)? as usize;
+            let [a, b] = [*program.get(offset)?, *program.get(offset + 1)?];
+            Some([a >> 4, a & 0xf, b >> 4, b & 0xf])
+        };
+
+        let mut platform = Platform::Chip8;
+        let mut visited = HashSet::new();
+        let mut pending = vec![Memory::PROGRAM_ENTRYPOINT];
+        while let Some(addr) = pending.pop() {
+            if !visited.insert(addr) {
+                continue;
+            }
+            let Some(nibbles) = nibbles_at(addr) else {
+                continue;
+            };
+            let next = addr + INSTRUCTION_SIZE;
+            match nibbles {
+                // Two-page hires clear
+                [0, 2, 3, 0] if hires_patch => return Platform::HiRes,
+                // Long I, audio, pitch, planes, register ranges, scroll up
+                [0xf, 0, 0, 0] | [0xf, 0, 0, 2] | [0xf, _, 3, 0xa] | [0xf, _, 0, 1]
+                | [5, _, _, 2] | [5, _, _, 3] | [0, 0, 0xd, _] => return Platform::XoChip,
+                // Scroll, resolution, big font, flags, 16x16 sprites
+                [0, 0, 0xc, _] | [0, 0, 0xf, 0xb | 0xc | 0xe | 0xf] | [0xf, _, 3, 0]
+                | [0xf, _, 7, 5] | [0xf, _, 8, 5] | [0xd, _, _, 0] => {
+                    platform = Platform::SuperChip
+                }
+                // Exit
+                [0, 0, 0xf, 0xd] => {
+                    platform = Platform::SuperChip;
+                    continue;
+                }
+                _ => {}
+            }
+            match Instruction::decode(nibbles, Platform::Chip8) {
+                None
+                | Some(Instruction::ReturnFromSubroutine)
+                | Some(Instruction::JumpToOffset(_)) => {}
+                Some(Instruction::JumpTo(target)) => pending.push(target),
+                Some(Instruction::ExecuteSubroutine(target)) => pending.extend([target, next]),
+                Some(
+                    Instruction::SkipEqTo(..)
+                    | Instruction::SkipNeqTo(..)
+                    | Instruction::SkipEq(..)
+                    | Instruction::SkipNeq(..)
+                    | Instruction::SkipIfKeyPressed(_)
+                    | Instruction::SkipIfKeyNotPressed(_),
+                ) => pending.extend([next, next + INSTRUCTION_SIZE]),
+                Some(_) => pending.push(next),
+            }
+        }
+        platform
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|platform| platform.name() == s)
+            .ok_or_else(|| format!("unknown platform {s:?}, expected chip-8, schip, xo-chip, hires, chip-8x or megachip"))
+    }
+}
+
+impl TryFrom<String> for Platform {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
@@ -0,0 +1,18 @@
+/// Behaviours that differ between CHIP-8 interpreters
+///
+/// All quirks are off by default: shifts read VY, BNNN adds V0 and sprites are
+/// clipped like on the COSMAC VIP, while I is left untouched by FX55/FX65 and
+/// VF is kept by logical operations like on most modern interpreters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VX in place and ignore VY
+    pub shift: bool,
+    /// FX55/FX65 leave I pointing after the last register
+    pub memory_increment: bool,
+    /// BNNN jumps to XNN + VX instead of NNN + V0
+    pub jump: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to 0
+    pub vf_reset: bool,
+    /// Sprites wrap around the screen edges instead of being clipped
+    pub wrap: bool,
+}
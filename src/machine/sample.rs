@@ -0,0 +1,56 @@
+//! MegaChip digitised sound, played by 060N
+//!
+//! The sound at I starts with a 6-byte header: the sample rate in Hz on 2
+//! bytes, the number of samples on 3 bytes, and a reserved byte. The samples
+//! follow, 8-bit unsigned.
+
+use std::sync::Arc;
+
+use thiserror::Error;
+
+use super::{memory, Address, Memory};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sample {
+    /// In Hz
+    pub rate: u32,
+    /// 8-bit unsigned, shared with the audio thread
+    pub data: Arc<[u8]>,
+    /// Start over at the end instead of stopping
+    pub looping: bool,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    MemoryError(#[from] memory::Error),
+    /// It would never get past its first sample
+    #[error("sound at {0:#05x} has a sample rate of 0 Hz")]
+    ZeroRate(Address),
+}
+
+impl Sample {
+    pub const HEADER_SIZE: Address = 6;
+
+    /// Sound at `addr`, read like instructions do
+    pub fn read(memory: &Memory, addr: Address, looping: bool) -> Result<Self, Error> {
+        let header = memory.read_span(addr, Self::HEADER_SIZE)?;
+        let rate = u32::from(u16::from_be_bytes([header[0], header[1]]));
+        if rate == 0 {
+            return Err(Error::ZeroRate(addr));
+        }
+        let len = u32::from_be_bytes([0, header[2], header[3], header[4]]);
+        let data = memory.read_span(addr.wrapping_add(Self::HEADER_SIZE), len)?;
+        Ok(Self {
+            rate,
+            data: data.into(),
+            looping,
+        })
+    }
+
+    /// Whether both were started by the same 060N, unlike `==` which compares
+    /// the samples
+    pub fn same_start(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.data, &other.data)
+    }
+}
@@ -1,35 +1,440 @@
-pub struct Screen {
-    pixels: [[bool; Self::WIDTH]; Self::HEIGHT],
+pub type Rgb = [u8; 3];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub foreground: Rgb,
+    pub background: Rgb,
 }
 
-impl Default for Screen {
+impl Default for Palette {
     fn default() -> Self {
         Self {
-            pixels: [[false; Self::WIDTH]; Self::HEIGHT],
+            foreground: [0xff, 0xff, 0xff],
+            background: [0x00, 0x00, 0x00],
+        }
+    }
+}
+
+pub struct Screen {
+    width: usize,
+    height: usize,
+    /// Row by row
+    pixels: Vec<bool>,
+    palette: Palette,
+    /// CHIP-8X colours, replacing the palette when present
+    colour_board: Option<ColourBoard>,
+    /// MegaChip colours, replacing the pixels while enabled
+    framebuffer: Option<Framebuffer>,
+    /// Set whenever `framebuffer` changed since the last [Screen::start_step]
+    framebuffer_changed: bool,
+    /// Set whenever `pixels` changed since the last [Screen::refresh_texture]
+    dirty: bool,
+    texture: image::Handle,
+}
+
+/// VP-590 colour board of the CHIP-8X, colouring lit pixels by cells 8 pixels
+/// wide and 1 pixel high, over one of 4 background colours
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColourBoard {
+    /// Cells in a row
+    columns: usize,
+    /// Colour of each cell, row by row, see [ColourBoard::COLOURS]
+    cells: Vec<u8>,
+    /// Index in [ColourBoard::BACKGROUNDS]
+    background: usize,
+}
+
+impl ColourBoard {
+    /// Foreground colours, by their 3-bit red, blue, green code
+    pub const COLOURS: [Rgb; 8] = [
+        [0x00, 0x00, 0x00],
+        [0xff, 0x00, 0x00],
+        [0x00, 0x00, 0xff],
+        [0xff, 0x00, 0xff],
+        [0x00, 0xff, 0x00],
+        [0xff, 0xff, 0x00],
+        [0x00, 0xff, 0xff],
+        [0xff, 0xff, 0xff],
+    ];
+    /// Background colours, in the order 02A0 cycles through them
+    pub const BACKGROUNDS: [Rgb; 4] = [
+        [0x00, 0x00, 0x80],
+        [0x00, 0x00, 0x00],
+        [0x00, 0x80, 0x00],
+        [0x80, 0x00, 0x00],
+    ];
+    /// Cell width, in pixels
+    pub const CELL_WIDTH: usize = 8;
+    /// Rows of a zone set by BXY0
+    pub const ZONE_HEIGHT: usize = 4;
+    /// Colour of the cells until set, red
+    const DEFAULT_COLOUR: u8 = 1;
+
+    fn new(width: usize, height: usize) -> Self {
+        let columns = width.div_ceil(Self::CELL_WIDTH);
+        Self {
+            columns,
+            cells: vec![Self::DEFAULT_COLOUR; columns * height],
+            background: 0,
+        }
+    }
+
+    /// Colour code of the cell at `column` and `row`, see [ColourBoard::COLOURS]
+    pub fn cell(&self, column: usize, row: usize) -> u8 {
+        self.cells[row * self.columns + column]
+    }
+
+    pub fn background(&self) -> Rgb {
+        Self::BACKGROUNDS[self.background]
+    }
+}
+
+/// How MegaChip sprites mix with the colours under them, set by 080N
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Blend {
+    #[default]
+    Normal,
+    /// Sprites at 25% opacity
+    Alpha25,
+    Alpha50,
+    Alpha75,
+    /// Colours added, saturating
+    Add,
+    Multiply,
+}
+
+impl Blend {
+    /// Mode set by 080N, if N is one
+    pub fn from_code(code: u8) -> Option<Self> {
+        Some(match code {
+            0 => Blend::Normal,
+            1 => Blend::Alpha25,
+            2 => Blend::Alpha50,
+            3 => Blend::Alpha75,
+            4 => Blend::Add,
+            5 => Blend::Multiply,
+            _ => return None,
+        })
+    }
+
+    /// N of the 080N setting it
+    pub fn code(self) -> u8 {
+        match self {
+            Blend::Normal => 0,
+            Blend::Alpha25 => 1,
+            Blend::Alpha50 => 2,
+            Blend::Alpha75 => 3,
+            Blend::Add => 4,
+            Blend::Multiply => 5,
+        }
+    }
+
+    fn mix(self, sprite: Rgb, screen: Rgb) -> Rgb {
+        let channel = |sprite: u8, screen: u8| {
+            let (sprite, screen) = (sprite as u16, screen as u16);
+            let mixed = match self {
+                Blend::Normal => sprite,
+                Blend::Alpha25 => (sprite + screen * 3) / 4,
+                Blend::Alpha50 => (sprite + screen) / 2,
+                Blend::Alpha75 => (sprite * 3 + screen) / 4,
+                Blend::Add => (sprite + screen).min(0xff),
+                Blend::Multiply => sprite * screen / 0xff,
+            };
+            mixed as u8
+        };
+        [0, 1, 2].map(|index| channel(sprite[index], screen[index]))
+    }
+}
+
+/// MegaChip 256x192 colour framebuffer, shown instead of the pixels while
+/// enabled by 0011
+///
+/// Sprites are drawn with the colours of their palette indices, and only
+/// shown on screen by the next 00E0, which clears the framebuffer for the
+/// next frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    enabled: bool,
+    /// Palette index drawn last at each pixel, row by row, 0 where none was
+    indices: Vec<u8>,
+    /// Colour of each pixel, row by row
+    colours: Vec<Rgb>,
+    /// `colours` as of the last 00E0
+    shown: Vec<Rgb>,
+    /// Colour of each palette index, loaded by 02NN without the alpha byte
+    /// of the ARGB colours, index 0 is transparent
+    pub palette: [Rgb; 256],
+    pub blend: Blend,
+    /// Opacity of the whole screen, set by 05NN
+    pub alpha: u8,
+    /// Sprites drawn over pixels of this palette index collide, set by 09NN,
+    /// pixels nothing was drawn on never do
+    pub collision_index: u8,
+    /// In pixels, set by 03NN
+    pub sprite_width: usize,
+    /// In pixels, set by 04NN
+    pub sprite_height: usize,
+}
+
+impl Framebuffer {
+    pub const WIDTH: usize = 256;
+    pub const HEIGHT: usize = 192;
+
+    fn new() -> Self {
+        let pixels = Self::WIDTH * Self::HEIGHT;
+        Self {
+            enabled: false,
+            indices: vec![0; pixels],
+            colours: vec![[0; 3]; pixels],
+            shown: vec![[0; 3]; pixels],
+            palette: [[0; 3]; 256],
+            blend: Blend::default(),
+            alpha: 0xff,
+            collision_index: 0,
+            sprite_width: 0,
+            sprite_height: 0,
         }
     }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Palette index drawn last at (x, y)
+    pub fn index(&self, x: usize, y: usize) -> u8 {
+        self.indices[y * Self::WIDTH + x]
+    }
+
+    /// Colour drawn at (x, y), shown by the next 00E0
+    pub fn colour(&self, x: usize, y: usize) -> Rgb {
+        self.colours[y * Self::WIDTH + x]
+    }
+
+    /// Colour shown at (x, y), before the screen opacity
+    pub fn shown(&self, x: usize, y: usize) -> Rgb {
+        self.shown[y * Self::WIDTH + x]
+    }
+
+    fn clear(&mut self) {
+        self.indices.fill(0);
+        self.colours.fill([0; 3]);
+    }
+}
+
+impl Default for Screen {
+    fn default() -> Self {
+        Self::new(Self::WIDTH, Self::HEIGHT)
+    }
 }
 
 impl Screen {
-    pub const SCALE: f32 = 20.0;
+    /// Size of the CHIP-8 screen
     pub const WIDTH: usize = 64;
     pub const HEIGHT: usize = 32;
-    pub const SIZE: Size<f32> = Size::new(Self::WIDTH as f32, Self::HEIGHT as f32);
 
+    pub fn new(width: usize, height: usize) -> Self {
+        let mut screen = Self {
+            width,
+            height,
+            pixels: vec![false; width * height],
+            palette: Palette::default(),
+            colour_board: None,
+            framebuffer: None,
+            framebuffer_changed: true,
+            dirty: true,
+            texture: image::Handle::from_rgba(0, 0, Vec::new()),
+        };
+        screen.refresh_texture();
+        screen
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn size(&self) -> Size<f32> {
+        Size::new(self.width as f32, self.height as f32)
+    }
+
+    /// Whether each pixel is lit, row by row
+    pub fn pixels(&self) -> &[bool] {
+        &self.pixels
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.dirty = true;
+    }
+
+    pub fn colour_board(&self) -> Option<&ColourBoard> {
+        self.colour_board.as_ref()
+    }
+
+    /// Colour the screen with a CHIP-8X colour board, instead of the palette
+    pub fn add_colour_board(&mut self) {
+        self.colour_board = Some(ColourBoard::new(self.width, self.height));
+        self.dirty = true;
+    }
+
+    pub fn framebuffer(&self) -> Option<&Framebuffer> {
+        self.framebuffer.as_ref()
+    }
+
+    /// Marks the screen to be redrawn, for changes of opacity
+    pub fn framebuffer_mut(&mut self) -> Option<&mut Framebuffer> {
+        self.dirty = true;
+        self.framebuffer_changed = true;
+        self.framebuffer.as_mut()
+    }
+
+    /// Give the screen a MegaChip framebuffer, disabled until
+    /// [Screen::enable_framebuffer]
+    pub fn add_framebuffer(&mut self) {
+        self.framebuffer = Some(Framebuffer::new());
+        self.framebuffer_changed = true;
+    }
+
+    /// Track the changes of the framebuffer from now on, see [Screen::framebuffer_changed]
+    pub fn start_step(&mut self) {
+        self.framebuffer_changed = false;
+    }
+
+    /// Whether the framebuffer changed since the last [Screen::start_step],
+    /// so it is only compared when drawn on, cleared or switched
+    pub fn framebuffer_changed(&self) -> bool {
+        self.framebuffer_changed
+    }
+
+    /// 0011: Show the framebuffer, cleared, on a 256x192 screen. Without a
+    /// framebuffer nothing happens
+    pub fn enable_framebuffer(&mut self) {
+        if let Some(framebuffer) = &mut self.framebuffer {
+            framebuffer.enabled = true;
+            framebuffer.clear();
+            framebuffer.shown.fill([0; 3]);
+            self.framebuffer_changed = true;
+            self.resize(Framebuffer::WIDTH, Framebuffer::HEIGHT);
+        }
+    }
+
+    /// 0010: Show the pixels again, cleared, on a `width` x `height` screen
+    pub fn disable_framebuffer(&mut self, width: usize, height: usize) {
+        if let Some(framebuffer) = &mut self.framebuffer {
+            framebuffer.enabled = false;
+            self.framebuffer_changed = true;
+            self.resize(width, height);
+        }
+    }
+
+    fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.pixels = vec![false; width * height];
+        self.dirty = true;
+    }
+
+    /// Clear the pixels, and the framebuffer after showing it when enabled
     pub fn clear(&mut self) {
-        self.pixels = [[false; Self::WIDTH]; Self::HEIGHT]
+        self.pixels.fill(false);
+        if let Some(framebuffer) = self.framebuffer.as_mut().filter(|framebuffer| framebuffer.enabled) {
+            std::mem::swap(&mut framebuffer.shown, &mut framebuffer.colours);
+            framebuffer.clear();
+            self.framebuffer_changed = true;
+        }
+        self.dirty = true;
+    }
+
+    /// 02A0: Move to the next background colour, without a colour board
+    /// nothing happens
+    pub fn cycle_background(&mut self) {
+        if let Some(board) = &mut self.colour_board {
+            board.background = (board.background + 1) % ColourBoard::BACKGROUNDS.len();
+            self.dirty = true;
+        }
+    }
+
+    /// Set the colour of the cells from `column` and `row` on, `columns` wide
+    /// and `rows` high, cut at the edges of the screen
+    pub fn set_colour(&mut self, column: usize, row: usize, columns: usize, rows: usize, colour: u8) {
+        let Some(board) = &mut self.colour_board else {
+            return;
+        };
+        for row in row..(row + rows).min(self.height) {
+            for column in column..(column + columns).min(board.columns) {
+                board.cells[row * board.columns + column] = colour % ColourBoard::COLOURS.len() as u8;
+            }
+        }
+        self.dirty = true;
+    }
+
+    /// Colour of the pixel at (x, y)
+    fn colour(&self, x: usize, y: usize) -> Rgb {
+        if let Some(framebuffer) = self.framebuffer.as_ref().filter(|framebuffer| framebuffer.enabled) {
+            let alpha = framebuffer.alpha as u16;
+            return framebuffer.shown(x, y).map(|channel| (channel as u16 * alpha / 0xff) as u8);
+        }
+        let lit = self.pixel(x, y);
+        match &self.colour_board {
+            Some(board) if lit => ColourBoard::COLOURS[board.cell(x / ColourBoard::CELL_WIDTH, y) as usize],
+            Some(board) => board.background(),
+            None if lit => self.palette.foreground,
+            None => self.palette.background,
+        }
+    }
+
+    /// Upload `pixels` to a new texture if they changed since the last call
+    ///
+    /// Unchanged frames keep the same [image::Handle], which the renderer
+    /// already has in its cache, so nothing is re-uploaded
+    pub fn refresh_texture(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        let rgba = (0..self.height)
+            .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+            .flat_map(|(x, y)| {
+                let [r, g, b] = self.colour(x, y);
+                [r, g, b, 0xff]
+            })
+            .collect::<Vec<u8>>();
+        self.texture = image::Handle::from_rgba(self.width as u32, self.height as u32, rgba);
+        self.dirty = false;
     }
 
-    // TODO: Should wrap around the screen
-    pub fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+    /// Draw a sprite with its top left corner at (x, y), wrapped around the screen
+    ///
+    /// Parts of the sprite going over the edges are clipped, or wrap around with `wrap`
+    pub fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8], wrap: bool) -> bool {
         log::trace!("draw_sprite: x: {x} y: {y}, sprite: {:x}", sprite.as_ptr() as usize);
+        let (x, y) = (x % self.width, y % self.height);
         let mut colision_found = false;
         for (line, &sprite_line) in sprite.iter().enumerate() {
-            let Some(screen_line) = self.pixels.get_mut(y + line) else {
-                break;
+            let line = match wrap {
+                true => (y + line) % self.height,
+                false => y + line,
             };
+            if line >= self.height {
+                break;
+            }
+            let screen_line = &mut self.pixels[line * self.width..(line + 1) * self.width];
             for column in 0..(u8::BITS as usize) {
-                let Some(image_pixel) = screen_line.get_mut(x + column) else {
+                let screen_column = match wrap {
+                    true => (x + column) % self.width,
+                    false => x + column,
+                };
+                let Some(image_pixel) = screen_line.get_mut(screen_column) else {
                     break;
                 };
                 let sprite_pixel = ((sprite_line >> (u8::BITS as usize - column - 1)) & 1) != 0;
@@ -37,19 +442,56 @@ impl Screen {
                 *image_pixel ^= sprite_pixel;
             }
         }
+        self.dirty = true;
         colision_found
     }
+
+    /// Draw a MegaChip sprite of palette indices, row by row, with its top
+    /// left corner at (x, y) of the framebuffer
+    ///
+    /// Index 0 is transparent. Parts going over the edges are clipped.
+    /// Returns whether a pixel of [Framebuffer::collision_index] was drawn over,
+    /// and nothing is drawn without an enabled framebuffer
+    pub fn draw_indexed_sprite(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+        let Some(framebuffer) = self.framebuffer.as_mut().filter(|framebuffer| framebuffer.enabled) else {
+            return false;
+        };
+        let width = framebuffer.sprite_width;
+        let mut collision_found = false;
+        for (row, indices) in sprite.chunks(width.max(1)).enumerate() {
+            let screen_y = y + row;
+            if screen_y >= Framebuffer::HEIGHT {
+                break;
+            }
+            for (column, &index) in indices.iter().enumerate() {
+                let screen_x = x + column;
+                if screen_x >= Framebuffer::WIDTH {
+                    break;
+                }
+                if index == 0 {
+                    continue;
+                }
+                let pixel = screen_y * Framebuffer::WIDTH + screen_x;
+                let under = framebuffer.indices[pixel];
+                collision_found |= under != 0 && under == framebuffer.collision_index;
+                framebuffer.indices[pixel] = index;
+                let colour = framebuffer.palette[index as usize];
+                framebuffer.colours[pixel] = framebuffer.blend.mix(colour, framebuffer.colours[pixel]);
+            }
+        }
+        self.framebuffer_changed = true;
+        collision_found
+    }
 }
 
 use iced::{
-    advanced::{graphics::core::event, layout, mouse, renderer, widget, Layout, Widget},
-    window::RedrawRequest,
-    Background, Color, Length, Rectangle, Shadow, Size,
+    advanced::{image, layout, mouse, renderer, widget, Layout, Widget},
+    Length, Rectangle, Size,
 };
 
 impl<M, T, R> Widget<M, T, R> for &Screen
 where
-    R: iced::advanced::Renderer,
+    R: image::Renderer<Handle = image::Handle>,
 {
     fn size(&self) -> Size<Length> {
         Size::new(Length::Fill, Length::Fill)
@@ -63,16 +505,16 @@ where
     ) -> layout::Node {
         let max_size = limits.max();
 
-        let too_narrow =
-            max_size.width * Screen::SIZE.height < Screen::SIZE.width * max_size.height;
+        let size = Screen::size(self);
+        let too_narrow = max_size.width * size.height < size.width * max_size.height;
 
         let ratio = match too_narrow {
-            true => max_size.width / Screen::SIZE.width,
-            false => max_size.height / Screen::SIZE.height,
+            true => max_size.width / size.width,
+            false => max_size.height / size.height,
         };
 
         // TODO: apply limits.min()
-        layout::Node::new(Screen::SIZE * ratio)
+        layout::Node::new(size * ratio)
     }
 
     fn draw(
@@ -86,60 +528,9 @@ where
         _viewport: &Rectangle,
     ) {
         log::trace!("Render!");
-        let bounds = layout.bounds();
-        let border = iced::Border::default();
-        let shadow = Shadow::default();
-
-        renderer.fill_quad(
-            renderer::Quad {
-                bounds,
-                border,
-                shadow,
-            },
-            Background::Color(Color::BLACK),
-        );
-
-        let scale = bounds.width / Screen::SIZE.width;
-
-        for (y, line) in self.pixels.iter().enumerate() {
-            for (x, &pixel) in line.iter().enumerate() {
-                if pixel {
-                    let bounds = Rectangle {
-                        x: x as f32 * scale,
-                        y: y as f32 * scale,
-                        width: scale,
-                        height: scale,
-                    };
-                    renderer.fill_quad(
-                        renderer::Quad {
-                            bounds,
-                            border,
-                            shadow,
-                        },
-                        Background::Color(Color::WHITE),
-                    );
-                }
-            }
-        }
-    }
-
-    fn on_event(
-        &mut self,
-        _state: &mut widget::Tree,
-        event: iced::Event,
-        _layout: Layout<'_>,
-        _cursor: mouse::Cursor,
-        _renderer: &R,
-        _clipboard: &mut dyn iced::advanced::Clipboard,
-        shell: &mut iced::advanced::Shell<'_, M>,
-        _viewport: &Rectangle,
-    ) -> event::Status {
-        if let iced::Event::Window(e) = event {
-            if let iced::window::Event::RedrawRequested(_) = e {
-                shell.request_redraw(RedrawRequest::NextFrame);
-                return event::Status::Captured;
-            }
-        }
-        event::Status::Ignored
+        let image = image::Image::new(self.texture.clone())
+            .filter_method(image::FilterMethod::Nearest)
+            .snap(true);
+        renderer.draw_image(image, layout.bounds());
     }
 }
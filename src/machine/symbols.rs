@@ -0,0 +1,108 @@
+//! Labels and source lines of an assembled program, to show addresses as
+//! `main_loop+4` and instructions as the source they were assembled from
+//!
+//! Symbol files are TOML, in a format of this emulator that no assembler
+//! writes, so the labels assemblers list have to be converted to it, see the
+//! README:
+//!
+//! ```toml
+//! labels = { main = 0x200, main_loop = 0x204 }
+//!
+//! [[lines]]
+//! address = 0x204
+//! file = "game.8o"
+//! line = 12
+//! source = "v0 += 1"
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use super::Address;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("cannot read symbols {0}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("invalid symbols {0}: {1}")]
+    Parse(PathBuf, toml::de::Error),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SourceLine {
+    pub address: Address,
+    pub file: String,
+    pub line: u32,
+    pub source: String,
+}
+
+impl fmt::Display for SourceLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct SymbolFile {
+    #[serde(default)]
+    labels: HashMap<String, Address>,
+    #[serde(default)]
+    lines: Vec<SourceLine>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    labels: BTreeMap<Address, String>,
+    lines: BTreeMap<Address, SourceLine>,
+}
+
+impl Symbols {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(path).map_err(|e| Error::Read(path.into(), e))?;
+        let file: SymbolFile = toml::from_str(&text).map_err(|e| Error::Parse(path.into(), e))?;
+        Ok(Self {
+            labels: file.labels.into_iter().map(|(label, addr)| (addr, label)).collect(),
+            lines: file.lines.into_iter().map(|line| (line.address, line)).collect(),
+        })
+    }
+
+    /// Symbols of the ROM at `rom`, from `<rom>.sym.toml` when it exists
+    pub fn find(rom: &Path) -> Result<Self, Error> {
+        let path = rom.with_extension("sym.toml");
+        match path.exists() {
+            true => {
+                log::info!("loading symbols {}", path.display());
+                Self::load(&path)
+            }
+            false => Ok(Self::default()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty() && self.lines.is_empty()
+    }
+
+    /// Label exactly at `addr`
+    pub fn label(&self, addr: Address) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str)
+    }
+
+    /// `addr` relative to the closest label before it, like `main_loop+4`,
+    /// or in hex without one
+    pub fn locate(&self, addr: Address) -> String {
+        match self.labels.range(..=addr).next_back() {
+            Some((label_addr, label)) if *label_addr == addr => label.clone(),
+            Some((label_addr, label)) => format!("{label}+{}", addr - label_addr),
+            None => format!("{addr:03X}"),
+        }
+    }
+
+    /// Source line assembled at `addr`
+    pub fn line(&self, addr: Address) -> Option<&SourceLine> {
+        self.lines.get(&addr)
+    }
+}
@@ -1,87 +1,541 @@
-mod machine;
+mod audio;
+mod config;
+mod database;
+mod keymap;
+mod keypad_view;
+mod memory_view;
+mod profile;
+mod rom_browser;
+mod trace;
 
-use clap::Parser;
+use audio::{Audio, AudioConfig};
+use clap::{Parser, ValueEnum};
+use config::{
+    CallStackConfig, Config, HexColor, Layer, MemoryConfig, PaletteConfig, QuirksConfig, Settings,
+    WindowConfig,
+};
+use database::Database;
 use iced::keyboard::Key;
-use machine::{instruction, Machine, Screen};
-use std::path::PathBuf;
-
-use rodio::source::{SineWave, Source};
-use rodio::{OutputStream, Sink};
+use keymap::Keymap;
+use chip_8::machine;
+use machine::instruction::dissassemble;
+use machine::lockstep::{Engine, LockStep, Side};
+use machine::{Machine, Platform, Symbols};
+use memory_view::MemoryView;
+use rom_browser::RomFile;
+use std::path::{Path, PathBuf};
 
 #[derive(Parser)]
-struct CLA {
-    program: PathBuf,
+struct Cla {
+    /// ROM to run, pick one in the ROM browser when missing
+    program: Option<PathBuf>,
     #[arg(short, long)]
     debug: bool,
+    /// Show a clickable keypad next to the screen
+    #[arg(short, long)]
+    keypad: bool,
+    /// Show the memory next to the screen, F9 toggles it
+    #[arg(short, long)]
+    memory: bool,
+    /// Configuration file, defaults to `~/.config/chip-8/config.toml`
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Directory listed by the ROM browser, defaults to `programs`
+    #[arg(long, value_name = "DIR")]
+    roms: Option<PathBuf>,
+    /// Platform the program was written for, guessed when unknown: chip-8, schip, xo-chip,
+    /// hires, chip-8x or megachip
+    ///
+    /// Resets the quirks, speed, call stack depth and 0NNN handling to those of the platform
+    #[arg(long)]
+    platform: Option<Platform>,
+    /// Instructions per frame
+    #[arg(long)]
+    speed: Option<u32>,
+    /// Quirks to enable, or to disable with a `no-` prefix, like `shift,no-jump`
+    #[arg(long, value_delimiter = ',', value_parser = QuirkSwitch::parse)]
+    quirks: Vec<QuirkSwitch>,
+    /// What 0NNN machine-code calls do: ignore, error or emulate the known VIP routines
+    #[arg(long, value_name = "MODE")]
+    machine_code: Option<machine::MachineCode>,
+    /// What accesses past the end of memory do: wrap, error or error-with-context
+    #[arg(long)]
+    memory_overflow: Option<machine::Overflow>,
+    /// Regions of memory the program cannot write to
+    #[arg(long, value_delimiter = ',')]
+    protect: Vec<Protect>,
+    /// Subroutines the call stack holds: 12, 16 or unbounded
+    #[arg(long, value_name = "DEPTH")]
+    stack_depth: Option<machine::StackDepth>,
+    /// Stop the program when 00EE returns after an instruction that is not a 2NNN call
+    #[arg(long)]
+    check_returns: bool,
+    /// Color of lit pixels, as #rrggbb
+    #[arg(long)]
+    foreground: Option<HexColor>,
+    /// Color of unlit pixels, as #rrggbb
+    #[arg(long)]
+    background: Option<HexColor>,
+    /// Size of a CHIP-8 pixel on screen
+    #[arg(long, value_parser = config::parse_scale)]
+    scale: Option<f32>,
+    /// Beep frequency in Hz
+    #[arg(long, value_parser = audio::parse_frequency)]
+    frequency: Option<f32>,
+    #[arg(long)]
+    waveform: Option<audio::Waveform>,
+    /// Beep volume, between 0 and 1
+    #[arg(long, value_parser = audio::parse_volume)]
+    volume: Option<f32>,
+    /// Start muted, F2 toggles mute
+    #[arg(long)]
+    mute: bool,
+    /// Write the sound to a WAV file instead of playing it
+    #[arg(long, value_name = "WAV")]
+    audio_out: Option<PathBuf>,
+    /// Write every instruction run, with the registers and memory it changed, to this file
+    #[arg(long, value_name = "FILE")]
+    trace: Option<PathBuf>,
+    /// Only trace instructions between these addresses, as START-END in hex
+    #[arg(long, value_name = "RANGE", value_parser = trace::parse_range, requires = "trace")]
+    trace_range: Option<std::ops::RangeInclusive<machine::Address>>,
+    /// Only write the last N instructions traced, once an instruction fails
+    #[arg(long, value_name = "N", requires = "trace")]
+    trace_last: Option<usize>,
+    /// Count the instructions run, then write a coverage and hotspot report to this file
+    #[arg(long, value_name = "FILE")]
+    profile: Option<PathBuf>,
+    /// Labels and source lines of the program, defaults to `<program>.sym.toml` when it exists
+    #[arg(long, value_name = "FILE", requires = "program")]
+    symbols: Option<PathBuf>,
+    /// Print the disassembly of the program and exit
+    #[arg(long, requires = "program")]
+    disassemble: bool,
+    /// Run this many frames without opening a window
+    #[arg(long, value_name = "FRAMES", requires = "program")]
+    headless: Option<u32>,
+    /// Seed of the random numbers drawn by CXNN, for runs that can be replayed
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Run the program as compiled basic blocks instead of interpreting it
+    #[cfg(feature = "jit")]
+    #[arg(long)]
+    jit: bool,
+    /// Run the program a second time with these quirks toggled, and report the
+    /// first instruction after which both runs differ
+    #[arg(long, value_name = "QUIRKS", value_delimiter = ',', requires = "headless")]
+    compare_quirks: Vec<Quirk>,
+    /// Run the program a second time with the JIT, and report the first
+    /// instruction after which it differs from the interpreter
+    #[cfg(feature = "jit")]
+    #[arg(long, requires = "headless")]
+    compare_jit: bool,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq)]
+enum Quirk {
+    Shift,
+    MemoryIncrement,
+    Jump,
+    VfReset,
+    Wrap,
+}
+
+/// Quirk given to `--quirks`, on unless prefixed by `no-`
+#[derive(Clone, Copy)]
+struct QuirkSwitch {
+    quirk: Quirk,
+    on: bool,
+}
+
+impl QuirkSwitch {
+    fn parse(s: &str) -> Result<Self, String> {
+        let (name, on) = match s.strip_prefix("no-") {
+            Some(name) => (name, false),
+            None => (s, true),
+        };
+        let quirk = Quirk::from_str(name, false)?;
+        Ok(Self { quirk, on })
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq)]
+enum Protect {
+    Font,
+    Program,
+}
+
+impl Quirk {
+    fn toggle(self, quirks: &mut machine::Quirks) {
+        let quirk = match self {
+            Quirk::Shift => &mut quirks.shift,
+            Quirk::MemoryIncrement => &mut quirks.memory_increment,
+            Quirk::Jump => &mut quirks.jump,
+            Quirk::VfReset => &mut quirks.vf_reset,
+            Quirk::Wrap => &mut quirks.wrap,
+        };
+        *quirk = !*quirk;
+    }
+}
+
+impl Cla {
+    /// Settings given on the command line, overriding the configuration
+    fn overrides(&self) -> Layer {
+        // The last switch of a quirk wins
+        let quirk = |quirk| {
+            self.quirks
+                .iter()
+                .rev()
+                .find(|switch| switch.quirk == quirk)
+                .map(|switch| switch.on)
+        };
+        Layer {
+            platform: self.platform,
+            speed: self.speed,
+            quirks: QuirksConfig {
+                shift: quirk(Quirk::Shift),
+                memory_increment: quirk(Quirk::MemoryIncrement),
+                jump: quirk(Quirk::Jump),
+                vf_reset: quirk(Quirk::VfReset),
+                wrap: quirk(Quirk::Wrap),
+            },
+            machine_code: self.machine_code,
+            memory: MemoryConfig {
+                overflow: self.memory_overflow,
+                protect_font: self.protect.contains(&Protect::Font).then_some(true),
+                protect_program: self.protect.contains(&Protect::Program).then_some(true),
+            },
+            call_stack: CallStackConfig {
+                depth: self.stack_depth,
+                check_returns: self.check_returns.then_some(true),
+            },
+            palette: PaletteConfig {
+                foreground: self.foreground,
+                background: self.background,
+            },
+            keymap: Default::default(),
+            audio: AudioConfig {
+                frequency: self.frequency,
+                waveform: self.waveform,
+                volume: self.volume,
+                muted: self.mute.then_some(true),
+                ..AudioConfig::default()
+            },
+            window: WindowConfig { scale: self.scale },
+        }
+    }
 }
 
 struct App {
+    pub title: String,
     pub debugging: bool,
+    pub show_keypad: bool,
+    pub show_memory: bool,
+    memory_view: MemoryView,
+    /// Show the ROM browser instead of the screen, the machine is paused meanwhile
+    show_browser: bool,
+    paused: bool,
+    /// Run [App::TURBO] frames per frame, while held
+    turbo: bool,
+    /// Run a frame every [App::SLOW_MOTION] frames
+    slow_motion: bool,
+    /// Frames since the last one run in slow motion
+    skipped_frames: u32,
+    config: Config,
+    database: Database,
+    /// Settings given on the command line
+    overrides: Layer,
+    rom_directory: PathBuf,
+    roms: Vec<RomFile>,
     machine: Machine,
-    _stream: OutputStream,
-    audio_sink: Sink,
+    /// Program loaded in the machine, loaded again on reset
+    program: Option<Vec<u8>>,
+    /// Size of a CHIP-8 pixel on screen, for the program loaded first
+    scale: f32,
+    keymap: Keymap,
+    audio: Box<dyn Audio>,
     last_draw: Option<std::time::Instant>,
+    /// Seed of every machine started, random when missing
+    seed: Option<u64>,
+    /// Why the machine stopped, shown over the screen until the next program
+    crash: Option<Box<machine::Crash>>,
+    /// Runs the machine instead of [Machine::run] when enabled
+    #[cfg(feature = "jit")]
+    jit: Option<machine::Jit>,
 }
 
 impl App {
-    fn new(machine: Machine) -> Self {
-        // _stream must live as long as the sink
-        let (_stream, stream_handle) = OutputStream::try_default().unwrap();
-        let audio_sink = Sink::try_new(&stream_handle).unwrap();
-
-        // Add a dummy source of the sake of the example.
-        let source = SineWave::new(440.0).amplify(0.20);
-        audio_sink.append(source);
-        audio_sink.pause();
+    const TURBO: u32 = 4;
+    const SLOW_MOTION: u32 = 4;
 
+    fn new(config: Config, overrides: Layer, rom_directory: PathBuf, audio: Box<dyn Audio>) -> Self {
         App {
+            title: "chip-8".to_string(),
             debugging: false,
-            machine,
-            _stream,
-            audio_sink,
+            show_keypad: false,
+            show_memory: false,
+            memory_view: MemoryView::default(),
+            show_browser: false,
+            paused: false,
+            turbo: false,
+            slow_motion: false,
+            skipped_frames: 0,
+            config,
+            database: Database::bundled(),
+            overrides,
+            rom_directory,
+            roms: Vec::new(),
+            machine: Machine::new(),
+            program: None,
+            scale: Settings::default().scale,
+            keymap: Keymap::default(),
+            audio,
             last_draw: None,
+            seed: None,
+            crash: None,
+            #[cfg(feature = "jit")]
+            jit: None,
+        }
+    }
+
+    fn load_rom(&mut self, path: &Path) -> Result<(), Box<dyn core::error::Error>> {
+        log::info!("loading {}", path.display());
+        let program = std::fs::read(path)?;
+        let symbols = match Symbols::find(path) {
+            Ok(symbols) => symbols,
+            Err(error) => {
+                log::warn!("{error}");
+                Symbols::default()
+            }
+        };
+        self.load_program(program, symbols)?;
+        Ok(())
+    }
+
+    /// Start `program` on a new machine, configured for it
+    fn load_program(
+        &mut self,
+        program: Vec<u8>,
+        symbols: Symbols,
+    ) -> Result<(), machine::MemoryError> {
+        let rom_hash = config::rom_hash(&program);
+        log::info!("rom sha1: {rom_hash}");
+
+        let entry = self.database.get(&rom_hash);
+        if let Some(entry) = entry {
+            match &entry.author {
+                Some(author) => log::info!("{} by {author}", entry.title),
+                None => log::info!("{}", entry.title),
+            }
+        }
+        let recommended = database::recommended(entry, &program);
+
+        let settings = self.config.settings(&rom_hash, &recommended, &self.overrides);
+        log::info!("platform: {}", settings.platform);
+
+        let mut machine = Machine::for_platform(settings.platform);
+        machine.observers = std::mem::take(&mut self.machine.observers);
+        machine.speed = settings.speed;
+        machine.quirks = settings.quirks;
+        machine.machine_code = settings.machine_code;
+        machine.memory.policy = settings.memory;
+        machine.call_stack.policy = settings.call_stack;
+        machine.screen.set_palette(settings.palette);
+        machine.symbols = symbols;
+        if let Some(seed) = self.seed {
+            machine.seed(seed);
+        }
+        machine.load_program(&program)?;
+        #[cfg(feature = "jit")]
+        if self.jit.is_some() {
+            self.jit = Some(machine::Jit::new());
+        }
+
+        self.title = match entry {
+            Some(entry) => format!("chip-8 - {}", entry.title),
+            None => "chip-8".to_string(),
+        };
+        if self.program.is_none() {
+            self.scale = settings.scale;
+        }
+        self.machine = machine;
+        self.program = Some(program);
+        self.crash = None;
+        self.keymap = settings.keymap;
+        self.audio.configure(settings.audio);
+        self.show_browser = false;
+        Ok(())
+    }
+
+    /// Start the loaded program over
+    fn reset(&mut self) {
+        if let Some(program) = self.program.take() {
+            let symbols = std::mem::take(&mut self.machine.symbols);
+            if let Err(error) = self.load_program(program, symbols) {
+                log::error!("cannot reset: {error}");
+            }
+        }
+    }
+
+    /// Move the machine to the left of a lock step run, against the same program
+    /// on a machine with `quirks` toggled, run by the JIT with `jit`
+    fn lock_step(&mut self, quirks: &[Quirk], jit: bool) -> Result<LockStep, machine::MemoryError> {
+        let seed = self.seed.unwrap_or_else(rand::random);
+        let mut left = std::mem::take(&mut self.machine);
+        left.seed(seed);
+
+        let mut right = Machine::for_platform(left.platform);
+        right.seed(seed);
+        right.speed = left.speed;
+        right.quirks = left.quirks;
+        right.machine_code = left.machine_code;
+        right.memory.policy = left.memory.policy;
+        right.call_stack.policy = left.call_stack.policy;
+        for quirk in quirks {
+            quirk.toggle(&mut right.quirks);
+        }
+        right.screen.set_palette(left.screen.palette());
+        right.load_program(self.program.as_deref().unwrap_or_default())?;
+
+        let right_engine = match jit {
+            #[cfg(feature = "jit")]
+            true => Engine::Jit(Box::new(machine::Jit::new())),
+            _ => Engine::Interpreter,
+        };
+        let mut changes = Vec::new();
+        if !matches!(right_engine, Engine::Interpreter) {
+            changes.push("jit".to_string());
+        }
+        changes.extend(
+            quirks
+                .iter()
+                .filter_map(|quirk| quirk.to_possible_value())
+                .map(|quirk| format!("{} toggled", quirk.get_name())),
+        );
+        Ok(LockStep::new(
+            Side::new("configured", left, Engine::Interpreter),
+            Side::new(changes.join(", "), right, right_engine),
+        ))
+    }
+
+    fn open_browser(&mut self) {
+        self.roms = match rom_browser::scan(&self.rom_directory, &self.database) {
+            Ok(roms) => roms,
+            Err(error) => {
+                log::warn!("cannot list {}: {error}", self.rom_directory.display());
+                Vec::new()
+            }
+        };
+        self.show_browser = true;
+        self.silence();
+    }
+
+    /// Stop the beep while the machine is paused, rather than leave it hanging,
+    /// the MegaChip sample picks up where it was on the next frame
+    fn silence(&mut self) {
+        self.audio.pause();
+    }
+
+    /// Stop the machine, showing `crash` until a program is loaded or reset
+    fn crashed(&mut self, crash: Box<machine::Crash>) {
+        log::error!("{crash}");
+        self.silence();
+        self.crash = Some(crash);
+    }
+
+    /// Instruction at IP, as its source line when there are symbols
+    fn position(&self) -> String {
+        let addr = self.machine.ip_register;
+        let symbols = &self.machine.symbols;
+        let text = match (symbols.line(addr), self.machine.current_instruction()) {
+            (Some(line), _) => format!("{} ({line})", line.source),
+            (None, Ok(instruction)) => instruction.to_string(),
+            (None, Err(error)) => error.to_string(),
+        };
+        format!("{}: {text}", symbols.locate(addr))
+    }
+
+    /// Shown over the screen, when not running normally
+    fn status(&self) -> Option<String> {
+        let mut status = Vec::new();
+        if self.paused {
+            status.push("paused".to_string());
+        }
+        if self.debugging {
+            status.push(format!("debugging {}", self.position()));
+        }
+        if self.turbo {
+            status.push(format!("turbo x{}", Self::TURBO));
+        } else if self.slow_motion {
+            status.push(format!("slow motion x1/{}", Self::SLOW_MOTION));
+        }
+        match status.is_empty() {
+            true => None,
+            false => Some(status.join(" | ")),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum Message {
     Render(iced::time::Instant),
+    KeyboardPressed(Key),
+    KeyboardReleased(Key),
     KeyPadPressed(machine::Key),
     KeyPadReleased(machine::Key),
     DebuggerStep,
+    ToggleMute,
+    ToggleBrowser,
+    /// Picked in the ROM browser or dropped on the window
+    LoadRom(PathBuf),
+    Reset,
+    TogglePause,
+    /// Run a single frame and pause
+    FrameAdvance,
+    ToggleSlowMotion,
+    Turbo(bool),
+    ToggleMemory,
+    MemorySelect(machine::Address),
+    MemoryInput(String),
+    MemoryWrite,
+    SpriteHeight(u8),
 }
 
-const WINDOW_SIZE: iced::Size = iced::Size {
-    width: Screen::WIDTH as f32 * Screen::SCALE,
-    height: Screen::HEIGHT as f32 * Screen::SCALE,
-};
+impl App {
+    /// Advance the machine by one 60 Hz frame
+    fn run_frame(&mut self) -> Result<(), Box<machine::Crash>> {
+        // Update clocks
+        self.machine.delay_timer = self.machine.delay_timer.saturating_sub(1);
+        self.machine.sound_timer = self.machine.sound_timer.saturating_sub(1);
 
-fn keymap(keyname: &str) -> Option<machine::Key> {
-    Some(match keyname {
-        "1" => 0x1,
-        "2" => 0x2,
-        "3" => 0x3,
-        "4" => 0xc,
-        "q" => 0x4,
-        "w" => 0x5,
-        "e" => 0x6,
-        "r" => 0xd,
-        "a" => 0x7,
-        "s" => 0x8,
-        "d" => 0x9,
-        "f" => 0xe,
-        "z" => 0xa,
-        "x" => 0x0,
-        "c" => 0xb,
-        "v" => 0xf,
-        _ => return None,
-    })
-}
+        // Manage Audio
+        self.audio.frame(
+            self.machine.sound_timer > 0,
+            audio::Pattern::of(&self.machine),
+            self.machine.sample.as_ref(),
+        );
+
+        // Run code
+        if !self.debugging {
+            #[cfg(feature = "jit")]
+            if let Some(jit) = &mut self.jit {
+                jit.run(&mut self.machine)?;
+                return Ok(());
+            }
+            self.machine.run()?;
+        }
+        Ok(())
+    }
+
+    /// Whether the machine can run: a program is loaded, the ROM browser is
+    /// closed and it did not crash
+    fn running(&self) -> bool {
+        self.program.is_some() && !self.show_browser && self.crash.is_none()
+    }
 
-impl App {
     fn update(&mut self, message: Message) {
         match message {
+            Message::Render(_) if !self.running() => {}
             Message::Render(last_draw) => {
                 // Frame rate log
                 if let Some(t) = self.last_draw {
@@ -90,45 +544,107 @@ impl App {
                 }
                 self.last_draw = Some(last_draw);
 
-                // Update clocks
-                self.machine.delay_timer = self.machine.delay_timer.saturating_sub(1);
-                self.machine.sound_timer = self.machine.sound_timer.saturating_sub(1);
-
-                // Manage Audio
-                if self.machine.sound_timer > 0 {
-                    self.audio_sink.play();
+                let frames = if self.paused {
+                    0
+                } else if self.turbo {
+                    Self::TURBO
+                } else if self.slow_motion {
+                    self.skipped_frames = (self.skipped_frames + 1) % Self::SLOW_MOTION;
+                    (self.skipped_frames == 0) as u32
                 } else {
-                    self.audio_sink.pause();
-                }
-
-                // Run code
-                if !self.debugging {
-                    match self.machine.run() {
-                        Ok(_) => {}
-                        Err(error) => panic!("{error}"),
+                    1
+                };
+                for _ in 0..frames {
+                    if let Err(crash) = self.run_frame() {
+                        self.crashed(crash);
+                        break;
                     }
                 }
 
-                // Reset keypad
-                self.machine.keypad.reset();
+                self.machine.screen.refresh_texture();
+            }
+            Message::KeyboardPressed(key) => {
+                if let Some(key) = self.keymap.get(&key) {
+                    self.update(Message::KeyPadPressed(key));
+                }
+                if let Some(key) = self.keymap.get_second(&key) {
+                    self.machine.keypad_2.press(key);
+                }
+            }
+            Message::KeyboardReleased(key) => {
+                if let Some(key) = self.keymap.get(&key) {
+                    self.update(Message::KeyPadReleased(key));
+                }
+                if let Some(key) = self.keymap.get_second(&key) {
+                    self.machine.keypad_2.release(key);
+                }
             }
             Message::KeyPadPressed(key) => self.machine.keypad.press(key),
             Message::KeyPadReleased(key) => self.machine.keypad.release(key),
+            Message::DebuggerStep if !self.running() => {}
             Message::DebuggerStep => {
-                match self.machine.step() {
-                    Ok(_) => {}
-                    Err(error) => panic!("{error}"),
+                if let Err(crash) = self.machine.step() {
+                    self.crashed(crash);
                 }
+                self.machine.screen.refresh_texture();
             },
+            Message::ToggleMute => self.audio.toggle_mute(),
+            Message::ToggleBrowser if !self.show_browser => self.open_browser(),
+            // Nothing to go back to without a program
+            Message::ToggleBrowser => self.show_browser = self.program.is_none(),
+            Message::LoadRom(path) => {
+                if let Err(error) = self.load_rom(&path) {
+                    log::error!("cannot load {}: {error}", path.display());
+                }
+            }
+            Message::Reset => self.reset(),
+            Message::TogglePause => {
+                self.paused = !self.paused;
+                if self.paused {
+                    self.silence();
+                }
+            }
+            Message::FrameAdvance if !self.running() => {}
+            Message::FrameAdvance => {
+                self.paused = true;
+                match self.run_frame() {
+                    Ok(()) => self.silence(),
+                    Err(crash) => self.crashed(crash),
+                }
+                self.machine.screen.refresh_texture();
+            }
+            Message::ToggleSlowMotion => self.slow_motion = !self.slow_motion,
+            Message::Turbo(turbo) => self.turbo = turbo,
+            Message::ToggleMemory => self.show_memory = !self.show_memory,
+            Message::MemorySelect(addr) => self.memory_view.select(addr, &self.machine.memory),
+            Message::MemoryInput(input) => self.memory_view.set_input(input),
+            Message::MemoryWrite => self.memory_view.write(&mut self.machine.memory),
+            Message::SpriteHeight(height) => self.memory_view.set_sprite_height(height),
         }
     }
 
-    fn view(&self) -> iced::Element<Message> {
-        // iced::widget::canvas(&self.machine.screen)
-        //     .width(iced::Length::Fixed(WINDOW_SIZE.width))
-        //     .height(iced::Length::Fixed(WINDOW_SIZE.height))
-        //     .into()
-        iced::Element::new(&self.machine.screen)
+    fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    fn view(&self) -> iced::Element<'_, Message> {
+        if self.show_browser {
+            return rom_browser::view(&self.rom_directory, &self.roms);
+        }
+        let mut screen = iced::Element::new(&self.machine.screen);
+        if let Some(crash) = &self.crash {
+            screen = iced::widget::stack![screen, crash_view(crash)].into();
+        } else if let Some(status) = self.status() {
+            screen = iced::widget::stack![screen, status_view(status)].into();
+        }
+        let mut panels = vec![screen];
+        if self.show_keypad {
+            panels.push(keypad_view::view(&self.machine.keypad));
+        }
+        if self.show_memory {
+            panels.push(self.memory_view.view(&self.machine));
+        }
+        iced::widget::row(panels).into()
     }
 
     fn subscription(&self) -> iced::Subscription<Message> {
@@ -136,51 +652,190 @@ impl App {
         // let frames = iced::time::every(frame_delay).map(Message::Render);
         let frames = iced::window::frames().map(Message::Render);
 
-        let key_pressed = iced::keyboard::on_key_press(|key, _modifier| match key {
-            Key::Character(c) => Some(Message::KeyPadPressed(keymap(c.as_str())?)),
-            _ => None,
-        });
+        let key_pressed =
+            iced::keyboard::on_key_press(|key, _modifier| Some(Message::KeyboardPressed(key)));
 
-        let key_release = iced::keyboard::on_key_release(|key, _modifier| match key {
-            Key::Character(c) => Some(Message::KeyPadReleased(keymap(c.as_str())?)),
-            _ => None,
+        let key_release =
+            iced::keyboard::on_key_release(|key, _modifier| Some(Message::KeyboardReleased(key)));
+
+        let hotkeys = iced::keyboard::on_key_press(|key, _modifier| {
+            use iced::keyboard::key::Named;
+            match key {
+                Key::Named(Named::Enter) => Some(Message::DebuggerStep),
+                Key::Named(Named::F1) => Some(Message::ToggleBrowser),
+                Key::Named(Named::F2) => Some(Message::ToggleMute),
+                Key::Named(Named::F5) => Some(Message::TogglePause),
+                Key::Named(Named::F6) => Some(Message::FrameAdvance),
+                Key::Named(Named::F7) => Some(Message::ToggleSlowMotion),
+                Key::Named(Named::F8) => Some(Message::Reset),
+                Key::Named(Named::F9) => Some(Message::ToggleMemory),
+                Key::Named(Named::Tab) => Some(Message::Turbo(true)),
+                _ => None,
+            }
         });
 
-        let debugger_step = iced::keyboard::on_key_press(|key, _modifier| {
+        let hotkey_releases = iced::keyboard::on_key_release(|key, _modifier| {
+            use iced::keyboard::key::Named;
             match key {
-                Key::Named(iced::keyboard::key::Named::Enter) => Some(Message::DebuggerStep),
+                Key::Named(Named::Tab) => Some(Message::Turbo(false)),
                 _ => None,
             }
         });
 
+        let file_drops = iced::event::listen_with(|event, _status, _window| match event {
+            iced::Event::Window(iced::window::Event::FileDropped(path)) => {
+                Some(Message::LoadRom(path))
+            }
+            _ => None,
+        });
+
         iced::Subscription::batch([
             key_pressed,
             key_release,
             frames,
-            debugger_step,
+            hotkeys,
+            hotkey_releases,
+            file_drops,
         ])
     }
 }
 
+/// Small label in the corner of the screen
+fn status_view<'a>(status: String) -> iced::Element<'a, Message> {
+    use iced::widget::{container, text};
+
+    let label = container(text(status).size(16))
+        .padding([2, 6])
+        .style(|_theme| container::Style {
+            text_color: Some(iced::Color::WHITE),
+            background: Some(iced::Color::from_rgba8(0, 0, 0, 0.6).into()),
+            ..container::Style::default()
+        });
+    container(label)
+        .padding(8)
+        .width(iced::Length::Fill)
+        .height(iced::Length::Fill)
+        .into()
+}
+
+/// Report of `crash` over the screen, with the ways out of it
+fn crash_view<'a>(crash: &machine::Crash) -> iced::Element<'a, Message> {
+    use iced::widget::{column, container, text};
+
+    let report = column![
+        text("crashed").size(20),
+        text(crash.to_string()).font(iced::Font::MONOSPACE).size(14),
+        text("F8 starts the program over, F1 opens another ROM").size(14),
+    ]
+    .spacing(8);
+    let report = container(report)
+        .padding(12)
+        .style(|_theme| container::Style {
+            text_color: Some(iced::Color::WHITE),
+            background: Some(iced::Color::from_rgba8(0, 0, 0, 0.8).into()),
+            ..container::Style::default()
+        });
+    container(report)
+        .padding(8)
+        .width(iced::Length::Fill)
+        .height(iced::Length::Fill)
+        .into()
+}
+
 fn main() -> Result<(), Box<dyn core::error::Error>> {
     env_logger::init();
 
 
-    let args = CLA::parse();
+    let args = Cla::parse();
 
-    let bytecode = std::fs::read(&args.program)?;
+    let config = Config::find(args.config.as_deref())?;
 
-    let mut machine = Machine::new();
+    // Audio settings of the ROM are only known once it is loaded
+    let settings = config.settings("", &Layer::default(), &args.overrides());
+    let audio = audio::open(settings.audio, args.audio_out.as_deref())?;
 
-    machine.load_program(&bytecode)?;
-
-    let mut app = App::new(machine);
+    let rom_directory = args
+        .roms
+        .clone()
+        .or_else(|| config.rom_directory.clone())
+        .unwrap_or_else(|| PathBuf::from("programs"));
 
+    let mut app = App::new(config, args.overrides(), rom_directory, audio);
     app.debugging = args.debug;
+    app.show_keypad = args.keypad;
+    app.show_memory = args.memory;
+    app.seed = args.seed;
+    #[cfg(feature = "jit")]
+    if args.jit {
+        app.jit = Some(machine::Jit::new());
+    }
+
+    if let Some(path) = &args.trace {
+        let tracer = trace::Tracer::create(path, args.trace_range.clone(), args.trace_last)?;
+        app.machine.observers.push(Box::new(tracer));
+    }
+    if let Some(path) = &args.profile {
+        let profiler = profile::Profiler::new(path.clone());
+        app.machine.observers.push(Box::new(profiler));
+    }
+
+    match &args.program {
+        Some(path) => app.load_rom(path)?,
+        None => app.open_browser(),
+    }
+    if let Some(path) = &args.symbols {
+        app.machine.symbols = Symbols::load(path)?;
+    }
+
+    if args.disassemble {
+        let program = app.program.as_deref().unwrap_or_default();
+        let symbols = &app.machine.symbols;
+        let platform = app.machine.platform;
+        print!("{}", dissassemble(program, platform.load_address(), platform, symbols));
+        return Ok(());
+    }
+
+    #[cfg(feature = "jit")]
+    let compare_jit = args.compare_jit;
+    #[cfg(not(feature = "jit"))]
+    let compare_jit = false;
+    if let (Some(frames), true) = (args.headless, compare_jit || !args.compare_quirks.is_empty()) {
+        let mut lock_step = app.lock_step(&args.compare_quirks, compare_jit)?;
+        for _ in 0..frames {
+            if let Err(error) = lock_step.run_frame() {
+                // Writes the trace and profile
+                drop(lock_step);
+                eprintln!("{error}");
+                std::process::exit(1);
+            }
+        }
+        println!("no difference in {} instructions", lock_step.steps());
+        return Ok(());
+    }
+
+    if let Some(frames) = args.headless {
+        for _ in 0..frames {
+            if let Err(crash) = app.run_frame() {
+                // Writes the trace and profile
+                drop(app);
+                eprintln!("{crash}");
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    let mut window_size = app.machine.screen.size() * app.scale;
+    if app.show_keypad {
+        window_size.width += keypad_view::WIDTH;
+    }
+    if app.show_memory {
+        window_size.width += memory_view::WIDTH;
+    }
 
-    iced::application("chip-8", App::update, App::view)
+    iced::application(App::title, App::update, App::view)
         .centered()
-        .window_size(WINDOW_SIZE)
+        .window_size(window_size)
         .subscription(App::subscription)
         .run_with(|| {
             (
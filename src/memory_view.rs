@@ -0,0 +1,210 @@
+//! Hex dump of the machine memory, with byte editing, the call stack and a
+//! preview of the sprite at I
+
+use iced::widget::text::Span;
+use iced::widget::{column, container, rich_text, row, scrollable, slider, span, text, text_input};
+use iced::{Color, Element, Font, Length};
+
+use crate::machine::{Address, Machine, Memory};
+use crate::Message;
+
+const BYTES_PER_ROW: usize = 16;
+const PIXEL_SIZE: f32 = 8.0;
+/// Rows of a DXYN sprite
+const MAX_SPRITE_HEIGHT: u8 = 15;
+/// Call stack frames listed, the oldest ones are left out
+const MAX_FRAMES: usize = 8;
+
+/// Width taken by the panel next to the screen
+pub const WIDTH: f32 = 520.0;
+
+const FONT_COLOR: Color = Color::from_rgb(0.5, 0.7, 1.0);
+const PROGRAM_COLOR: Color = Color::WHITE;
+const FREE_COLOR: Color = Color::from_rgb(0.45, 0.45, 0.45);
+const I_COLOR: Color = Color::from_rgb(0.6, 0.5, 0.0);
+const IP_COLOR: Color = Color::from_rgb(0.0, 0.5, 0.2);
+
+/// State of the panel
+pub struct MemoryView {
+    /// Byte being edited
+    selected: Option<Address>,
+    /// New value of the selected byte, in hex
+    input: String,
+    /// Rows of the sprite previewed at I
+    sprite_height: u8,
+}
+
+impl Default for MemoryView {
+    fn default() -> Self {
+        Self {
+            selected: None,
+            input: String::new(),
+            sprite_height: MAX_SPRITE_HEIGHT,
+        }
+    }
+}
+
+impl MemoryView {
+    pub fn select(&mut self, addr: Address, memory: &Memory) {
+        self.selected = Some(addr);
+        self.input = format!("{:02X}", memory.get(addr).unwrap_or_default());
+    }
+
+    pub fn set_input(&mut self, input: String) {
+        self.input = input;
+    }
+
+    pub fn set_sprite_height(&mut self, height: u8) {
+        self.sprite_height = height;
+    }
+
+    /// Write the input to the selected byte, if it is a valid hex byte
+    pub fn write(&mut self, memory: &mut Memory) {
+        let Some(addr) = self.selected else {
+            return;
+        };
+        match (u8::from_str_radix(self.input.trim(), 16), memory.get_mut(addr)) {
+            (Ok(value), Ok(byte)) => {
+                log::info!("memory edit: {addr:#05X} := {value:#04X}");
+                *byte = value;
+            }
+            _ => log::warn!("invalid byte {:?}", self.input),
+        }
+    }
+
+    pub fn view<'a>(&'a self, machine: &'a Machine) -> Element<'a, Message> {
+        let memory = machine.memory.range(0..machine.memory.end()).unwrap_or_default();
+        let program = machine.memory.program();
+        let program = program.start as usize..program.end as usize;
+        let sprite = sprite_range(machine.i_register, self.sprite_height, machine.memory.end());
+        let sprite = sprite.start as usize..sprite.end as usize;
+        let instruction = machine.ip_register as usize..machine.ip_register as usize + 2;
+
+        let byte_span = |addr: usize, byte: u8| -> Span<'a, Message> {
+            let color = if addr < Memory::FONT_SIZE as usize {
+                FONT_COLOR
+            } else if program.contains(&addr) {
+                PROGRAM_COLOR
+            } else {
+                FREE_COLOR
+            };
+            let (color, background) = if self.selected == Some(addr as Address) {
+                (Color::BLACK, Some(Color::WHITE))
+            } else if instruction.contains(&addr) {
+                (Color::WHITE, Some(IP_COLOR))
+            } else if sprite.contains(&addr) {
+                (Color::WHITE, Some(I_COLOR))
+            } else {
+                (color, None)
+            };
+            span(format!("{byte:02X}"))
+                .color(color)
+                .background_maybe(background)
+                .link(Message::MemorySelect(addr as Address))
+        };
+
+        // The MegaChip keeps graphics and samples above, too many rows to list
+        let listed = &memory[..memory.len().min(Memory::SIZE)];
+        let rows = listed.chunks(BYTES_PER_ROW).enumerate().map(|(index, bytes)| {
+            let start = index * BYTES_PER_ROW;
+            let mut spans = vec![span(format!("{start:03X} ")).color(FREE_COLOR)];
+            for (offset, byte) in bytes.iter().enumerate() {
+                spans.push(span(" "));
+                spans.push(byte_span(start + offset, *byte));
+            }
+            rich_text(spans).font(Font::MONOSPACE).size(13).into()
+        });
+        let dump = scrollable(column(rows)).height(Length::Fill);
+
+        let legend = rich_text([
+            span("font ").color(FONT_COLOR),
+            span("program ").color(PROGRAM_COLOR),
+            span(" IP ").background(IP_COLOR),
+            span(" "),
+            span(" I ").background(I_COLOR),
+        ])
+        .size(13);
+
+        let editor = match self.selected {
+            Some(addr) => row![
+                text(format!("{addr:03X} :=")).font(Font::MONOSPACE),
+                text_input("hex byte", &self.input)
+                    .font(Font::MONOSPACE)
+                    .width(Length::Fixed(60.0))
+                    .on_input(Message::MemoryInput)
+                    .on_submit(Message::MemoryWrite),
+            ]
+            .spacing(8)
+            .into(),
+            None => Element::from(text("click a byte to edit it").size(13)),
+        };
+
+        let sprite_bytes = memory.get(sprite).unwrap_or_default();
+        let sprite_title = format!(
+            "sprite at I = {:03X}, 8x{}",
+            machine.i_register, self.sprite_height
+        );
+        let sprite_view = column![
+            text(sprite_title).size(13),
+            slider(1..=MAX_SPRITE_HEIGHT, self.sprite_height, Message::SpriteHeight)
+                .width(Length::Fixed(8.0 * PIXEL_SIZE * 2.0)),
+            sprite_preview(sprite_bytes),
+        ]
+        .spacing(6);
+
+        container(column![legend, dump, editor, stack_view(machine), sprite_view].spacing(8))
+            .width(Length::Fixed(WIDTH))
+            .height(Length::Fill)
+            .padding(8)
+            .into()
+    }
+}
+
+/// Subroutines called, the running one first, with their labels when there are symbols
+fn stack_view<'a>(machine: &Machine) -> Element<'a, Message> {
+    let stack = &machine.call_stack;
+    let symbols = &machine.symbols;
+    let mut lines = vec![format!("call stack {}/{}", stack.depth(), stack.policy.depth)];
+    for frame in stack.frames().rev().take(MAX_FRAMES) {
+        let mut line = format!("{:03X} from {:03X}", frame.subroutine, frame.call);
+        if !symbols.is_empty() {
+            line = format!(
+                "{line}  {} from {}",
+                symbols.locate(frame.subroutine),
+                symbols.locate(frame.call)
+            );
+        }
+        lines.push(line);
+    }
+    if stack.depth() > MAX_FRAMES {
+        lines.push(format!("{} more", stack.depth() - MAX_FRAMES));
+    }
+    text(lines.join("\n")).font(Font::MONOSPACE).size(13).into()
+}
+
+/// Bytes at I, cut at the `end` of the memory
+fn sprite_range(i: Address, height: u8, end: Address) -> std::ops::Range<Address> {
+    i.min(end)..(i.saturating_add(height as Address)).min(end)
+}
+
+/// Bytes as rows of 8 pixels, most significant bit on the left
+fn sprite_preview<'a>(bytes: &[u8]) -> Element<'a, Message> {
+    let pixel = |lit: bool| {
+        let color = match lit {
+            true => Color::WHITE,
+            false => Color::from_rgb(0.15, 0.15, 0.15),
+        };
+        container(text(""))
+            .width(Length::Fixed(PIXEL_SIZE))
+            .height(Length::Fixed(PIXEL_SIZE))
+            .style(move |_theme| container::Style {
+                background: Some(color.into()),
+                ..container::Style::default()
+            })
+            .into()
+    };
+    column(bytes.iter().map(|byte| {
+        row((0..u8::BITS).rev().map(|bit| pixel(byte >> bit & 1 == 1))).into()
+    }))
+    .into()
+}
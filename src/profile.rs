@@ -0,0 +1,355 @@
+//! Coverage and hotspot profiler, see `--profile`
+//!
+//! Counts the instructions run per address and per opcode, the loops taken
+//! and the subroutines called, then writes a report once the run is over.
+//! Loading another program starts a new report, written after the previous ones
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+use crate::config;
+use crate::machine::instruction::Instruction;
+use crate::machine::{Address, Machine, Memory, Observer, RunFlow, RunResult};
+
+/// Entries listed in each section of the report
+const TOP: usize = 10;
+
+/// Subroutine being run
+struct Frame {
+    addr: Address,
+    /// [Profiler::instructions] when it was called
+    start: u64,
+}
+
+#[derive(Default)]
+struct Subroutine {
+    calls: u64,
+    /// Instructions run until it returned, including the subroutines it called
+    instructions: u64,
+}
+
+pub struct Profiler {
+    path: PathBuf,
+    instructions: u64,
+    /// Grown as addresses run
+    per_address: Vec<u64>,
+    per_pattern: HashMap<&'static str, u64>,
+    /// Backward jumps and skips, as (from, to)
+    loops: HashMap<(Address, Address), u64>,
+    subroutines: HashMap<Address, Subroutine>,
+    frames: Vec<Frame>,
+    /// Before the current instruction
+    ip_register: Address,
+    depth: usize,
+    /// Program up to its last non-zero byte, to find code never run
+    program: Option<std::ops::RangeInclusive<Address>>,
+    /// Program profiled, none until the first instruction or program loaded
+    loaded: Option<Vec<u8>>,
+    /// Reports of the programs loaded before
+    reports: Vec<String>,
+}
+
+impl Profiler {
+    /// The report is written to `path` when the profiler is dropped
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            instructions: 0,
+            per_address: vec![0; Memory::SIZE],
+            per_pattern: HashMap::new(),
+            loops: HashMap::new(),
+            subroutines: HashMap::new(),
+            frames: Vec::new(),
+            ip_register: 0,
+            depth: 0,
+            program: None,
+            loaded: None,
+            reports: Vec::new(),
+        }
+    }
+
+    /// Profile the program loaded in `machine` from now on
+    fn start(&mut self, machine: &Machine) {
+        let range = machine.memory.program();
+        let start = range.start;
+        let loaded = machine.memory.range(range).unwrap_or_default();
+        self.program = loaded
+            .iter()
+            .rposition(|byte| *byte != 0)
+            .map(|offset| start..=start + offset as Address);
+        self.loaded = Some(loaded.to_vec());
+    }
+
+    /// Every report, the one of the program loaded last
+    fn reports(&self) -> String {
+        if self.reports.is_empty() {
+            return self.report();
+        }
+        let mut reports = self.reports.join("\n");
+        if self.instructions > 0 {
+            reports.push('\n');
+            reports.push_str(&self.report());
+        }
+        reports
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        100.0 * count as f64 / self.instructions.max(1) as f64
+    }
+
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        if let Some(loaded) = &self.loaded {
+            let _ = writeln!(report, "program: {}", config::rom_hash(loaded));
+        }
+        let _ = writeln!(report, "instructions run: {}", self.instructions);
+
+        let mut addresses: Vec<_> = (0..self.per_address.len())
+            .filter(|&addr| self.per_address[addr] > 0)
+            .collect();
+        addresses.sort_by_key(|&addr| std::cmp::Reverse(self.per_address[addr]));
+        let _ = writeln!(report, "\n# hottest addresses\naddr  count       %");
+        for addr in addresses.into_iter().take(TOP) {
+            let count = self.per_address[addr];
+            let _ = writeln!(report, "{addr:03X}  {count:<10}  {:5.1}", self.percent(count));
+        }
+
+        let mut patterns: Vec<_> = self.per_pattern.iter().collect();
+        patterns.sort_by_key(|(_, count)| std::cmp::Reverse(**count));
+        let _ = writeln!(report, "\n# opcodes\nopcode  count       %");
+        for (pattern, count) in patterns {
+            let _ = writeln!(report, "{pattern}    {count:<10}  {:5.1}", self.percent(*count));
+        }
+
+        let mut loops: Vec<_> = self.loops.iter().collect();
+        loops.sort_by_key(|(_, count)| std::cmp::Reverse(**count));
+        let _ = writeln!(report, "\n# hottest loops\nloop      taken       instructions run inside");
+        for ((from, to), taken) in loops.into_iter().take(TOP) {
+            let run: u64 = self.per_address[*to as usize..=*from as usize].iter().sum();
+            let _ = writeln!(report, "{to:03X}-{from:03X}  {taken:<10}  {run}");
+        }
+
+        let mut subroutines: Vec<_> = self.subroutines.iter().collect();
+        subroutines.sort_by_key(|(_, subroutine)| std::cmp::Reverse(subroutine.instructions));
+        let _ = writeln!(report, "\n# subroutines\naddr  calls       instructions  per call");
+        for (addr, subroutine) in subroutines {
+            let per_call = subroutine.instructions as f64 / subroutine.calls.max(1) as f64;
+            let _ = writeln!(
+                report,
+                "{addr:03X}  {:<10}  {:<12}  {per_call:.1}",
+                subroutine.calls, subroutine.instructions
+            );
+        }
+
+        let _ = writeln!(report, "\n# never run, code or data\nrange     bytes");
+        for range in self.never_run() {
+            let _ = writeln!(report, "{:03X}-{:03X}  {}", range.start, range.end - 1, range.len());
+        }
+        report
+    }
+
+    /// Ranges of the program not covered by any instruction run
+    fn never_run(&self) -> Vec<std::ops::Range<usize>> {
+        let Some(program) = &self.program else {
+            return Vec::new();
+        };
+        let (program_start, end) = (*program.start(), *program.end());
+        let mut covered = vec![false; self.per_address.len().max(end as usize + 1)];
+        for (addr, count) in self.per_address.iter().enumerate() {
+            if *count > 0 {
+                covered[addr] = true;
+                if let Some(next) = covered.get_mut(addr + 1) {
+                    *next = true;
+                }
+            }
+        }
+        let mut ranges = Vec::new();
+        let mut start = None;
+        let program = covered
+            .iter()
+            .enumerate()
+            .take(end as usize + 1)
+            .skip(program_start as usize);
+        for (addr, covered) in program {
+            match (*covered, start) {
+                (false, None) => start = Some(addr),
+                (true, Some(from)) => {
+                    ranges.push(from..addr);
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some(from) = start {
+            ranges.push(from..end as usize + 1);
+        }
+        ranges
+    }
+}
+
+impl Observer for Profiler {
+    fn program_loaded(&mut self, machine: &Machine) {
+        let range = machine.memory.program();
+        let program = machine.memory.range(range).unwrap_or_default();
+        // Resetting the same program adds up
+        if self.loaded.as_deref() == Some(program) {
+            return;
+        }
+        if self.instructions > 0 {
+            self.reports.push(self.report());
+        }
+        self.instructions = 0;
+        self.per_address.fill(0);
+        self.per_pattern.clear();
+        self.loops.clear();
+        self.subroutines.clear();
+        self.frames.clear();
+        self.start(machine);
+    }
+
+    fn before_step(&mut self, machine: &Machine) {
+        // Loaded before the profiler was added
+        if self.loaded.is_none() {
+            self.start(machine);
+        }
+        self.ip_register = machine.ip_register;
+        self.depth = machine.call_stack.depth();
+    }
+
+    fn after_step(&mut self, machine: &Machine, result: &RunResult) {
+        if result.is_err() {
+            return;
+        }
+        let addr = self.ip_register;
+        self.instructions += 1;
+        if self.per_address.len() <= addr as usize {
+            self.per_address.resize(addr as usize + 1, 0);
+        }
+        self.per_address[addr as usize] += 1;
+        let nibbles = machine.memory.nibbles_at(addr);
+        let decode = |nibbles| Instruction::decode(nibbles, machine.platform);
+        if let Ok(Some(instruction)) = nibbles.map(decode) {
+            *self.per_pattern.entry(instruction.pattern()).or_default() += 1;
+        }
+
+        let depth = machine.call_stack.depth();
+        // Returns go backward as well, and key waits run the same instruction
+        // again without looping
+        let waiting = matches!(result, Ok(RunFlow::Wait));
+        if machine.ip_register <= addr && depth == self.depth && !waiting {
+            *self.loops.entry((addr, machine.ip_register)).or_default() += 1;
+        }
+
+        if depth > self.depth {
+            self.subroutines.entry(machine.ip_register).or_default().calls += 1;
+            self.frames.push(Frame {
+                addr: machine.ip_register,
+                start: self.instructions,
+            });
+        } else if depth < self.depth {
+            if let Some(frame) = self.frames.pop() {
+                let subroutine = self.subroutines.entry(frame.addr).or_default();
+                subroutine.instructions += self.instructions - frame.start;
+            }
+        }
+    }
+}
+
+impl Drop for Profiler {
+    fn drop(&mut self) {
+        match std::fs::write(&self.path, self.reports()) {
+            Ok(()) => log::info!("profile written to {}", self.path.display()),
+            Err(error) => log::error!("cannot write profile {}: {error}", self.path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counts down v0 from 3 calling a subroutine each time, then waits for a key
+    const PROGRAM: [u8; 0x12] = [
+        0x60, 0x03, // v0 := 3
+        0x22, 0x0c, // call 0x20c
+        0x70, 0xff, // v0 += -1
+        0x30, 0x00, // if v0 != 0 then
+        0x12, 0x02, // jump 0x202
+        0xf1, 0x0a, // v1 := key
+        0x61, 0x05, // 0x20c: v1 := 5
+        0x00, 0xee, // return
+        0xaa, 0xbb, // data
+    ];
+
+    fn profiler(name: &str) -> Profiler {
+        Profiler::new(std::env::temp_dir().join(name))
+    }
+
+    fn run(profiler: &mut Profiler, machine: &mut Machine, steps: usize) {
+        for _ in 0..steps {
+            profiler.before_step(machine);
+            let result = machine.step();
+            profiler.after_step(machine, &result);
+        }
+    }
+
+    #[test]
+    fn counts() {
+        let mut profiler = profiler("chip-8-profile-counts.txt");
+        let mut machine = Machine::new();
+        machine.load_program(&PROGRAM).unwrap();
+        // Three turns of the loop, the last one skipping the jump, then three
+        // frames waiting for a key
+        run(&mut profiler, &mut machine, 21);
+        assert_eq!(machine.ip_register, 0x20a);
+
+        assert_eq!(profiler.instructions, 21);
+        let counts = [(0x200, 1), (0x202, 3), (0x208, 2), (0x20a, 3), (0x20c, 3), (0x20e, 3)];
+        for (addr, count) in counts {
+            assert_eq!(profiler.per_address[addr], count, "at {addr:03X}");
+        }
+        assert_eq!(profiler.per_pattern["2NNN"], 3);
+        assert_eq!(profiler.per_pattern["FX0A"], 3);
+
+        // Neither the returns nor the key wait are loops
+        assert_eq!(profiler.loops, HashMap::from([((0x208, 0x202), 2)]));
+
+        let subroutine = &profiler.subroutines[&0x20c];
+        assert_eq!((subroutine.calls, subroutine.instructions), (3, 6));
+
+        assert_eq!(profiler.never_run(), vec![(0x210..0x212)]);
+    }
+
+    #[test]
+    fn report_per_program() {
+        let mut profiler = profiler("chip-8-profile-programs.txt");
+        let mut machine = Machine::new();
+        machine.load_program(&PROGRAM).unwrap();
+        run(&mut profiler, &mut machine, 3);
+
+        // Starting the same program over adds up
+        let mut machine = Machine::new();
+        machine.load_program(&PROGRAM).unwrap();
+        profiler.program_loaded(&machine);
+        run(&mut profiler, &mut machine, 3);
+        assert_eq!(profiler.instructions, 6);
+
+        let other = [0x12, 0x00]; // jump 0x200
+        let mut machine = Machine::new();
+        machine.load_program(&other).unwrap();
+        profiler.program_loaded(&machine);
+        assert_eq!(profiler.instructions, 0);
+        assert!(profiler.subroutines.is_empty());
+        run(&mut profiler, &mut machine, 2);
+        assert_eq!(profiler.per_address[0x200], 2);
+        assert_eq!(profiler.per_address[0x202], 0);
+        assert!(profiler.never_run().is_empty());
+
+        let reports = profiler.reports();
+        let first = format!("program: {}\ninstructions run: 6\n", config::rom_hash(&PROGRAM));
+        let second = format!("program: {}\ninstructions run: 2\n", config::rom_hash(&other));
+        assert!(reports.starts_with(&first), "{reports}");
+        assert!(reports.contains(&second), "{reports}");
+    }
+}
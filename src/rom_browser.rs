@@ -0,0 +1,73 @@
+//! ROM picker listing the programs of a directory, with their database titles
+
+use std::path::{Path, PathBuf};
+
+use iced::widget::{button, column, container, scrollable, text};
+use iced::{Element, Length};
+
+use crate::config::rom_hash;
+use crate::database::Database;
+use crate::Message;
+
+/// Extensions of the files listed
+const EXTENSIONS: [&str; 4] = ["ch8", "c8", "sc8", "xo8"];
+
+pub struct RomFile {
+    pub path: PathBuf,
+    /// Title from the database, or the file name
+    pub title: String,
+}
+
+/// List the ROMs of `directory`, sorted by title
+pub fn scan(directory: &Path, database: &Database) -> std::io::Result<Vec<RomFile>> {
+    let mut roms = Vec::new();
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        let is_rom = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| EXTENSIONS.contains(&extension.to_lowercase().as_str()));
+        if !is_rom {
+            continue;
+        }
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let title = match std::fs::read(&path) {
+            Ok(program) => match database.get(&rom_hash(&program)) {
+                Some(entry) => format!("{} ({file_name})", entry.title),
+                None => file_name,
+            },
+            Err(error) => {
+                log::warn!("cannot read {}: {error}", path.display());
+                continue;
+            }
+        };
+        roms.push(RomFile { path, title });
+    }
+    roms.sort_by_key(|rom| rom.title.to_lowercase());
+    Ok(roms)
+}
+
+pub fn view<'a>(directory: &'a Path, roms: &'a [RomFile]) -> Element<'a, Message> {
+    let header = text(format!("ROMs in {}", directory.display())).size(20);
+    let list: Element<'a, Message> = match roms.is_empty() {
+        true => text("No ROM found, drop a file on the window to run it").into(),
+        false => scrollable(
+            column(roms.iter().map(|rom| {
+                button(text(&rom.title))
+                    .width(Length::Fill)
+                    .style(button::text)
+                    .on_press(Message::LoadRom(rom.path.clone()))
+                    .into()
+            }))
+            .spacing(2),
+        )
+        .height(Length::Fill)
+        .into(),
+    };
+
+    container(column![header, list].spacing(12))
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .padding(16)
+        .into()
+}
@@ -0,0 +1,263 @@
+//! Execution trace written to a file, see `--trace`
+//!
+//! One line per instruction: its address, opcode, disassembly, then the
+//! registers and memory it changed. With symbols, the address is followed by
+//! its label and the disassembly is replaced by the source line.
+//!
+//! ```text
+//! 20C  6832  v8 := 50              v8: 00 -> 32
+//! 210  A4F1  i := 0x4f1            I: 000 -> 4F1
+//! ```
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use crate::machine::instruction::Instruction;
+use crate::machine::{Address, Machine, Memory, Observer, RunResult};
+
+/// `START-END` addresses in hex, both included
+pub fn parse_range(s: &str) -> Result<RangeInclusive<Address>, String> {
+    let invalid = || format!("invalid range {s:?}, expected START-END in hex, like 200-2ff");
+    let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+    let parse = |addr: &str| Address::from_str_radix(addr.trim_start_matches("0x"), 16);
+    match (parse(start), parse(end)) {
+        (Ok(start), Ok(end)) if start <= end => Ok(start..=end),
+        _ => Err(invalid()),
+    }
+}
+
+/// Machine state before an instruction, to find what it changed
+struct Before {
+    addr: Address,
+    opcode: Option<u16>,
+    registers: [u8; 16],
+    i_register: Address,
+    delay_timer: u8,
+    sound_timer: u8,
+}
+
+pub struct Tracer {
+    output: BufWriter<File>,
+    /// Only instructions at these addresses are traced
+    range: Option<RangeInclusive<Address>>,
+    /// Only keep this many instructions, written when one fails
+    last: Option<usize>,
+    lines: VecDeque<String>,
+    /// None while the current instruction is filtered out
+    before: Option<Before>,
+    /// Copy of the memory before the current instruction, kept up to date with
+    /// the bytes written since
+    memory: Vec<u8>,
+}
+
+impl Tracer {
+    pub fn create(
+        path: &Path,
+        range: Option<RangeInclusive<Address>>,
+        last: Option<usize>,
+    ) -> std::io::Result<Self> {
+        let mut output = BufWriter::new(File::create(path)?);
+        writeln!(output, "# addr opcode instruction changes")?;
+        Ok(Self {
+            output,
+            range,
+            last,
+            lines: VecDeque::new(),
+            before: None,
+            memory: Vec::with_capacity(Memory::SIZE),
+        })
+    }
+
+    fn record(&mut self, line: String) {
+        match self.last {
+            Some(0) => {}
+            Some(last) => {
+                if self.lines.len() >= last {
+                    self.lines.pop_front();
+                }
+                self.lines.push_back(line);
+            }
+            None => self.write(&line),
+        }
+    }
+
+    fn write(&mut self, line: &str) {
+        if let Err(error) = writeln!(self.output, "{line}") {
+            log::error!("cannot write trace: {error}");
+        }
+    }
+
+    /// Write the instructions kept so far, and make sure everything reached the file
+    fn dump(&mut self) {
+        if let Some(last) = self.last {
+            self.write(&format!("# last {last} instructions"));
+        }
+        while let Some(line) = self.lines.pop_front() {
+            self.write(&line);
+        }
+        if let Err(error) = self.output.flush() {
+            log::error!("cannot write trace: {error}");
+        }
+    }
+}
+
+impl Observer for Tracer {
+    fn before_step(&mut self, machine: &Machine) {
+        // Catch up with the instructions filtered out and the memory editor
+        let memory = machine.memory.range(0..machine.memory.end()).unwrap_or_default();
+        if self.memory.len() != memory.len() {
+            self.memory = memory.to_vec();
+        } else if let Some(written) = machine.memory.step_written() {
+            let written = written.start as usize..written.end as usize;
+            self.memory[written.clone()].copy_from_slice(&memory[written]);
+        }
+
+        let addr = machine.ip_register;
+        if self.range.as_ref().is_some_and(|range| !range.contains(&addr)) {
+            self.before = None;
+            return;
+        }
+        let opcode = machine.memory.opcode_at(addr);
+        self.before = Some(Before {
+            addr,
+            opcode,
+            registers: machine.registers,
+            i_register: machine.i_register,
+            delay_timer: machine.delay_timer,
+            sound_timer: machine.sound_timer,
+        });
+    }
+
+    fn after_step(&mut self, machine: &Machine, result: &RunResult) {
+        let Some(before) = self.before.take() else {
+            if result.is_err() {
+                self.dump();
+            }
+            return;
+        };
+
+        let symbols = &machine.symbols;
+        let instruction = before.opcode.and_then(|opcode| {
+            let [a, b] = opcode.to_be_bytes();
+            Instruction::decode([a >> 4, a & 0xf, b >> 4, b & 0xf], machine.platform)
+        });
+        let disassembly = match (symbols.line(before.addr), instruction) {
+            (Some(line), _) => line.source.clone(),
+            (None, Some(instruction)) => instruction.to_string(),
+            (None, None) => "???".to_string(),
+        };
+        let opcode = match before.opcode {
+            Some(opcode) => format!("{opcode:04X}"),
+            None => "----".to_string(),
+        };
+        let mut line = format!("{:03X}  ", before.addr);
+        if !symbols.is_empty() {
+            let _ = write!(line, "{:<16}  ", symbols.locate(before.addr));
+        }
+        let _ = write!(line, "{opcode}  {disassembly:<20}");
+
+        for (x, (old, new)) in before.registers.iter().zip(machine.registers).enumerate() {
+            if *old != new {
+                let _ = write!(line, "  v{x:X}: {old:02X} -> {new:02X}");
+            }
+        }
+        if before.i_register != machine.i_register {
+            let _ = write!(line, "  I: {:03X} -> {:03X}", before.i_register, machine.i_register);
+        }
+        if before.delay_timer != machine.delay_timer {
+            let _ = write!(line, "  DT: {:02X} -> {:02X}", before.delay_timer, machine.delay_timer);
+        }
+        if before.sound_timer != machine.sound_timer {
+            let _ = write!(line, "  ST: {:02X} -> {:02X}", before.sound_timer, machine.sound_timer);
+        }
+        if let Some(written) = machine.memory.step_written() {
+            let memory = machine.memory.range(written.clone()).unwrap_or_default();
+            let old = &mut self.memory[written.start as usize..written.end as usize];
+            for (addr, (old, new)) in written.zip(old.iter_mut().zip(memory)) {
+                if old != new {
+                    let _ = write!(line, "  [{addr:03X}]: {old:02X} -> {new:02X}");
+                    *old = *new;
+                }
+            }
+        }
+
+        match result {
+            Ok(_) => self.record(line.trim_end().to_string()),
+            Err(crash) => {
+                let _ = write!(line, "  error: {}", crash.error);
+                self.record(line);
+                self.dump();
+            }
+        }
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        if let Err(error) = self.output.flush() {
+            log::error!("cannot write trace: {error}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracer(name: &str, last: Option<usize>) -> Tracer {
+        Tracer::create(&std::env::temp_dir().join(name), None, last).unwrap()
+    }
+
+    #[test]
+    fn ring_buffer() {
+        let mut tracer = tracer("chip-8-trace-last-2.txt", Some(2));
+        for line in ["a", "b", "c"] {
+            tracer.record(line.to_string());
+        }
+        assert_eq!(tracer.lines, ["b", "c"]);
+    }
+
+    #[test]
+    fn memory_changes() {
+        let program = [
+            0xa3, 0x00, // i := 0x300
+            0x60, 0x05, // v0 := 5
+            0xf0, 0x55, // save v0
+            0xf0, 0x33, // bcd v0, at 0x206
+        ];
+        let run = |range| {
+            let path = std::env::temp_dir().join("chip-8-trace-memory.txt");
+            let mut tracer = Tracer::create(&path, range, Some(4)).unwrap();
+            let mut machine = Machine::new();
+            machine.load_program(&program).unwrap();
+            for _ in 0..4 {
+                tracer.before_step(&machine);
+                let result = machine.step();
+                tracer.after_step(&machine, &result);
+            }
+            std::mem::take(&mut tracer.lines)
+        };
+
+        let lines = run(None);
+        assert!(lines[2].ends_with("  [300]: 00 -> 05"), "{}", lines[2]);
+        assert!(lines[3].ends_with("  [300]: 05 -> 00  [302]: 00 -> 05"), "{}", lines[3]);
+
+        // Writes of the instructions filtered out are still accounted for
+        let lines = run(Some(0x206..=0x206));
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].ends_with("  [300]: 05 -> 00  [302]: 00 -> 05"), "{}", lines[0]);
+    }
+
+    #[test]
+    fn ring_buffer_empty() {
+        let mut tracer = tracer("chip-8-trace-last-0.txt", Some(0));
+        for line in ["a", "b", "c"] {
+            tracer.record(line.to_string());
+        }
+        assert!(tracer.lines.is_empty());
+    }
+}
@@ -0,0 +1,83 @@
+//! Call stack depth limits, frames and return checks
+
+mod common;
+
+use chip_8::machine::{Frame, Machine, Platform, StackDepth, TickError};
+
+/// Calls itself forever
+const RECURSE: [u8; 2] = [
+    0x22, 0x00, // call 0x200
+];
+
+/// A subroutine overwriting the call to it before returning
+const OVERWRITE_CALLER: [u8; 14] = [
+    0x22, 0x06, // call 0x206
+    0x12, 0x02, // jump 0x202
+    0x00, 0x00,
+    0xa2, 0x00, // i := 0x200
+    0x60, 0x00, // v0 := 0
+    0xf0, 0x55, // save v0
+    0x00, 0xee, // return
+];
+
+/// Steps run before the first error, at most `limit`
+fn steps_until_error(machine: &mut Machine, limit: usize) -> (usize, Option<TickError>) {
+    for step in 0..limit {
+        if let Err(crash) = machine.step() {
+            return (step, Some(crash.error));
+        }
+    }
+    (limit, None)
+}
+
+#[test]
+fn depth_limits() {
+    for (depth, expected) in [(StackDepth::Vip, 12), (StackDepth::Schip, 16)] {
+        let mut machine = recursing(depth);
+        let (steps, error) = steps_until_error(&mut machine, 100);
+        assert_eq!(steps, expected, "{depth}");
+        assert!(matches!(error, Some(TickError::StackError(_))), "{depth}: {error:?}");
+        assert_eq!(machine.call_stack.depth(), expected);
+    }
+
+    // Far deeper, but still stopping a runaway recursion
+    let mut machine = recursing(StackDepth::Unbounded);
+    let (steps, error) = steps_until_error(&mut machine, 100_000);
+    assert_eq!(steps, StackDepth::UNBOUNDED_LIMIT);
+    assert!(matches!(error, Some(TickError::StackError(_))), "{error:?}");
+}
+
+fn recursing(depth: StackDepth) -> Machine {
+    common::machine(Platform::Chip8, &RECURSE, |machine| {
+        machine.call_stack.policy.depth = depth
+    })
+}
+
+#[test]
+fn frames() {
+    let mut machine = common::machine(Platform::Chip8, &OVERWRITE_CALLER, |_| {});
+    machine.step().unwrap();
+    let frame = Frame {
+        call: 0x200,
+        subroutine: 0x206,
+    };
+    assert_eq!(machine.call_stack.frames().collect::<Vec<_>>(), [frame]);
+    assert_eq!(machine.call_stack.top().map(Frame::return_address), Some(0x202));
+}
+
+#[test]
+fn return_check() {
+    let mut unchecked = common::machine(Platform::Chip8, &OVERWRITE_CALLER, |_| {});
+    let (_, error) = steps_until_error(&mut unchecked, 10);
+    assert!(error.is_none(), "{error:?}");
+
+    let mut checked = common::machine(Platform::Chip8, &OVERWRITE_CALLER, |machine| {
+        machine.call_stack.policy.check_returns = true
+    });
+    let (steps, error) = steps_until_error(&mut checked, 10);
+    assert_eq!(steps, 4);
+    let error = error.unwrap().to_string();
+    assert_eq!(error, "return to 0x202, which does not follow a call");
+    // Still in the subroutine, for the crash report
+    assert_eq!(checked.call_stack.depth(), 1);
+}
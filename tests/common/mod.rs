@@ -0,0 +1,11 @@
+//! Setup shared by the integration tests
+
+use chip_8::machine::{Machine, Platform};
+
+/// Machine of `platform` with `program` loaded, once `setup` changed its settings
+pub fn machine(platform: Platform, program: &[u8], setup: impl FnOnce(&mut Machine)) -> Machine {
+    let mut machine = Machine::for_platform(platform);
+    setup(&mut machine);
+    machine.load_program(program).unwrap();
+    machine
+}
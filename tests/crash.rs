@@ -0,0 +1,54 @@
+//! Reports of failing instructions
+
+use chip_8::machine::{Machine, MemoryError, TickError};
+
+#[test]
+fn report() {
+    let program = [
+        0x60, 0xff, // v0 := 255
+        0xa3, 0x00, // i := 0x300
+        0x22, 0x08, // call 0x208
+        0x00, 0x00, // padding
+        0x50, 0x01, // unknown
+    ];
+    let mut machine = Machine::new();
+    machine.load_program(&program).unwrap();
+    machine.delay_timer = 0x3c;
+    for _ in 0..3 {
+        machine.step().unwrap();
+    }
+    let Err(crash) = machine.step() else {
+        panic!("5001 should fail");
+    };
+    assert!(matches!(crash.error, TickError::Unknown(0x5001)));
+    assert_eq!(crash.ip_register, 0x208);
+    assert_eq!(crash.stack, [0x206]);
+    assert_eq!(
+        crash.to_string(),
+        "\
+unknown instruction 0x5001
+  at       208
+  opcode   5001  unknown
+  V0-V7    FF 00 00 00 00 00 00 00
+  V8-VF    00 00 00 00 00 00 00 00
+  I        300
+  timers   DT 3C  ST 00
+  stack    depth 1: 206"
+    );
+}
+
+#[test]
+fn report_out_of_memory() {
+    let mut machine = Machine::new();
+    machine.ip_register = 0x1000;
+    let Err(crash) = machine.step() else {
+        panic!("fetching past the end of memory should fail");
+    };
+    assert!(matches!(
+        crash.error,
+        TickError::MemoryError(MemoryError::RangeOutOfBound(_))
+    ));
+    assert_eq!(crash.opcode, None);
+    let report = crash.to_string();
+    assert!(report.contains("\n  opcode   out of memory\n"), "{report}");
+}
@@ -0,0 +1,79 @@
+//! The JIT and the interpreter run the same programs in lock step, and must
+//! be in the same state after every instruction
+//!
+//! ```sh
+//! cargo test --features jit
+//! ```
+
+#![cfg(feature = "jit")]
+
+mod common;
+
+use chip_8::machine::lockstep::{Engine, Error, LockStep, Side};
+use chip_8::machine::{Jit, Machine, Platform};
+
+const FRAMES: u32 = 600;
+const SEED: u64 = 0xC8;
+
+/// Seeded, with the settings programs for `platform` usually expect
+fn seeded(program: &[u8], platform: Platform) -> Machine {
+    common::machine(platform, program, |machine| {
+        machine.seed(SEED);
+        machine.quirks = platform.quirks();
+        machine.speed = platform.speed();
+        machine.call_stack.policy.depth = platform.stack_depth();
+    })
+}
+
+/// Run both engines for [FRAMES] frames, pressing a different key every second
+fn lock_step(name: &str, program: &[u8], platform: Platform) -> Machine {
+    let interpreter = Side::new("interpreter", seeded(program, platform), Engine::Interpreter);
+    let jit = Side::new("jit", seeded(program, platform), Engine::Jit(Box::new(Jit::new())));
+    let mut lock_step = LockStep::new(interpreter, jit);
+
+    for frame in 0..FRAMES {
+        let key = (frame / 60) as u8 % 16;
+        match frame % 60 {
+            30 => lock_step.press(key),
+            40 => lock_step.release(key),
+            _ => {}
+        }
+        match lock_step.run_frame() {
+            Ok(()) => {}
+            Err(Error::Failed { .. }) => break,
+            Err(error) => panic!("{name}, frame {frame}: {error}"),
+        }
+    }
+    lock_step.right.machine
+}
+
+#[test]
+fn roms() {
+    let mut paths: Vec<_> = std::fs::read_dir("programs")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    paths.sort();
+    for path in paths {
+        let program = std::fs::read(&path).unwrap();
+        let platform = Platform::detect(&program);
+        lock_step(&path.display().to_string(), &program, platform);
+    }
+}
+
+#[test]
+fn self_modifying_code() {
+    let program = [
+        0xa2, 0x0b, // i := 0x20b, the value loaded into v1 below
+        0xf0, 0x65, // load v0
+        0x70, 0x01, // v0 += 1
+        0xa2, 0x0b, // i := 0x20b
+        0xf0, 0x55, // save v0
+        0x61, 0x00, // v1 := 0, rewritten on every pass
+        0x82, 0x14, // v2 += v1
+        0x12, 0x00, // jump 0x200
+    ];
+    let machine = lock_step("self-modifying", &program, Platform::Chip8);
+    // Running the block compiled on the first pass would keep v1 at 1
+    assert!(machine.registers[1] > 1);
+}
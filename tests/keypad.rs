@@ -0,0 +1,62 @@
+//! FX0A waits for a key to be pressed then released, catching keys tapped
+//! between two frames
+
+use chip_8::machine::{Keypad, Machine};
+
+/// v0 := key, then v1 := 1
+const WAIT: [u8; 4] = [0xf0, 0x0a, 0x61, 0x01];
+
+#[test]
+fn press_and_release_within_a_frame() {
+    let mut machine = Machine::new();
+    machine.load_program(&WAIT).unwrap();
+    machine.step().unwrap();
+    assert_eq!(machine.ip_register, 0x200);
+
+    // Both edges arrive before the next frame runs FX0A again
+    machine.keypad.press(0x7);
+    machine.keypad.release(0x7);
+    machine.step().unwrap();
+    assert_eq!(machine.ip_register, 0x202);
+    assert_eq!(machine.register(0), 0x7);
+}
+
+#[test]
+fn key_held_before_the_wait() {
+    let mut keypad = Keypad::default();
+    keypad.press(0x3);
+    assert_eq!(keypad.wait_for_key(), None);
+
+    // Releasing the key held when the wait started does not count
+    keypad.release(0x3);
+    assert_eq!(keypad.wait_for_key(), None);
+
+    keypad.press(0x3);
+    assert_eq!(keypad.wait_for_key(), None);
+    keypad.release(0x3);
+    assert_eq!(keypad.wait_for_key(), Some(0x3));
+}
+
+#[test]
+fn two_keys_queued() {
+    let mut keypad = Keypad::default();
+    assert_eq!(keypad.wait_for_key(), None);
+
+    // The first key pressed is the one waited for, whatever is released first
+    keypad.press(0x1);
+    keypad.press(0x2);
+    keypad.release(0x2);
+    assert_eq!(keypad.wait_for_key(), None);
+    keypad.release(0x1);
+    assert_eq!(keypad.wait_for_key(), Some(0x1));
+
+    // Both tapped in one frame: the first one is returned, and the next wait
+    // starts afresh
+    assert_eq!(keypad.wait_for_key(), None);
+    keypad.press(0x4);
+    keypad.release(0x4);
+    keypad.press(0x5);
+    keypad.release(0x5);
+    assert_eq!(keypad.wait_for_key(), Some(0x4));
+    assert_eq!(keypad.wait_for_key(), None);
+}
@@ -0,0 +1,56 @@
+//! Differential runs of two quirk presets
+
+mod common;
+
+use chip_8::machine::lockstep::{Engine, Error, LockStep, Side, Snapshot};
+use chip_8::machine::{Platform, RunFlow};
+
+/// Shifts V1 into V0, which only the shift quirk ignores
+const SHIFT: [u8; 8] = [
+    0x60, 0x03, // v0 := 3
+    0x61, 0x08, // v1 := 8
+    0x80, 0x16, // v0 >>= v1
+    0x12, 0x06, // jump 0x206
+];
+
+fn side(platform: Platform, program: &[u8]) -> Side {
+    let machine = common::machine(Platform::Chip8, program, |machine| {
+        machine.seed(0);
+        machine.quirks = platform.quirks();
+    });
+    Side::new(platform.name(), machine, Engine::Interpreter)
+}
+
+#[test]
+fn reports_first_divergence() {
+    let mut lock_step = LockStep::new(side(Platform::Chip8, &SHIFT), side(Platform::SuperChip, &SHIFT));
+    let Err(Error::Diverged(divergence)) = lock_step.run_frame() else {
+        panic!("chip-8 and schip shifts should diverge");
+    };
+    assert_eq!(divergence.step, 3);
+    assert_eq!(divergence.addr, 0x204);
+    assert_eq!(divergence.opcode, Some(0x8016));
+    assert_eq!(divergence.states[0].registers[0], 4);
+    assert_eq!(divergence.states[1].registers[0], 1);
+    assert!(divergence.to_string().contains("v0: 04 != 01"), "{divergence}");
+}
+
+#[test]
+fn same_presets_never_diverge() {
+    let program = std::fs::read("programs/3-corax+.ch8").unwrap();
+    let mut lock_step = LockStep::new(side(Platform::Chip8, &program), side(Platform::Chip8, &program));
+    for _ in 0..120 {
+        lock_step.run_frame().unwrap();
+    }
+    assert!(lock_step.steps() > 0);
+    assert!(matches!(lock_step.step(), Ok(RunFlow::Continue)));
+}
+
+#[test]
+fn memory_differences() {
+    let left = side(Platform::Chip8, &SHIFT).machine;
+    let mut right = side(Platform::Chip8, &SHIFT).machine;
+    *right.memory.get_mut(0x301).unwrap() = 5;
+    let states = [Snapshot::of(&left, 0x300..0x302, false), Snapshot::of(&right, 0x300..0x302, false)];
+    assert_eq!(states[0].differences(&states[1]), ["[301]: 00 != 05"]);
+}
@@ -0,0 +1,49 @@
+//! 0NNN handling modes
+
+mod common;
+
+use chip_8::machine::{MachineCode, Platform, TickError};
+
+#[test]
+fn ignore() {
+    let mut machine = common::machine(Platform::Chip8, &[0x01, 0x23], |machine| {
+        machine.machine_code = MachineCode::Ignore
+    });
+    machine.step().unwrap();
+    assert_eq!(machine.ip_register, 0x202);
+}
+
+#[test]
+fn error() {
+    let mut machine = common::machine(Platform::Chip8, &[0x02, 0x30], |machine| {
+        machine.machine_code = MachineCode::Error
+    });
+    let Err(crash) = machine.step() else {
+        panic!("0NNN should fail");
+    };
+    assert!(matches!(crash.error, TickError::Unimplemented(_)), "{crash}");
+    assert_eq!(machine.ip_register, 0x200);
+}
+
+#[test]
+fn emulate() {
+    let program = [
+        0xd0, 0x05, // sprite v0 v0 5, the 0 glyph at I = 0
+        0x02, 0x30, // two-page hires clear
+        0x02, 0xa0, // CHIP-8X background cycle, nothing to cycle
+        0x01, 0x23, // unknown routine
+    ];
+    let mut machine = common::machine(Platform::Chip8, &program, |machine| {
+        machine.machine_code = MachineCode::Emulate
+    });
+    machine.step().unwrap();
+    assert!(machine.screen.pixels().iter().any(|lit| *lit));
+    machine.step().unwrap();
+    assert!(machine.screen.pixels().iter().all(|lit| !lit));
+    machine.step().unwrap();
+    assert_eq!(machine.ip_register, 0x206);
+    let Err(crash) = machine.step() else {
+        panic!("unknown routines should fail");
+    };
+    assert!(matches!(crash.error, TickError::Unimplemented(_)), "{crash}");
+}
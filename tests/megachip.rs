@@ -0,0 +1,187 @@
+//! MegaChip mode, long I, palettes, indexed sprites and digitised sound
+
+mod common;
+
+use chip_8::machine::{Framebuffer, Machine, Platform, SampleError, TickError};
+
+const RED: [u8; 3] = [0xff, 0x00, 0x00];
+const GREEN: [u8; 3] = [0x00, 0xff, 0x00];
+const BLACK: [u8; 3] = [0x00, 0x00, 0x00];
+
+/// MegaChip with `program` at 0x200, then `data` at 0x220
+fn megachip(program: &[u8], data: &[u8]) -> Machine {
+    let mut rom = program.to_vec();
+    rom.resize(0x20, 0);
+    rom.extend_from_slice(data);
+    common::machine(Platform::MegaChip, &rom, |_| {})
+}
+
+fn run(machine: &mut Machine, steps: usize) {
+    for _ in 0..steps {
+        machine.step().unwrap();
+    }
+}
+
+fn framebuffer(machine: &Machine) -> &Framebuffer {
+    machine.screen.framebuffer().unwrap()
+}
+
+/// Red and green, as ARGB
+const PALETTE: [u8; 8] = [0xff, 0xff, 0x00, 0x00, 0xff, 0x00, 0xff, 0x00];
+
+#[test]
+fn detect() {
+    assert_eq!(Platform::detect(&[0x00, 0x11, 0x00, 0xe0]), Platform::MegaChip);
+    let machine = Machine::for_platform(Platform::MegaChip);
+    assert_eq!(machine.memory.size(), 0x100_0000);
+}
+
+#[test]
+fn mode() {
+    let program = [
+        0x00, 0x11, // megachip_on
+        0x00, 0x10, // megachip_off
+    ];
+    let mut machine = megachip(&program, &[]);
+    assert!(!framebuffer(&machine).enabled());
+    run(&mut machine, 1);
+    assert!(framebuffer(&machine).enabled());
+    assert_eq!((machine.screen.width(), machine.screen.height()), (256, 192));
+    run(&mut machine, 1);
+    assert!(!framebuffer(&machine).enabled());
+    assert_eq!((machine.screen.width(), machine.screen.height()), (64, 32));
+}
+
+#[test]
+fn framebuffer_changes() {
+    let program = [
+        0x60, 0x0a, // v0 := 10
+        0x00, 0x11, // megachip_on
+        0x61, 0x0b, // v1 := 11
+        0x00, 0xe0, // clear
+        0x00, 0x10, // megachip_off
+    ];
+    let mut machine = megachip(&program, &[]);
+    let mut changes = Vec::new();
+    for _ in 0..5 {
+        machine.screen.start_step();
+        run(&mut machine, 1);
+        changes.push(machine.screen.framebuffer_changed());
+    }
+    assert_eq!(changes, [false, true, false, true, true]);
+}
+
+#[test]
+fn long_i() {
+    let program = [
+        0x01, 0x12, 0x34, 0x56, // i := long 0x123456
+        0x00, 0xe0, // clear
+    ];
+    let mut machine = megachip(&program, &[]);
+    run(&mut machine, 1);
+    assert_eq!(machine.i_register, 0x123456);
+    assert_eq!(machine.ip_register, 0x204);
+}
+
+#[test]
+fn sprites() {
+    let program = [
+        0x00, 0x11, // megachip_on
+        0xa2, 0x20, // i := palette
+        0x02, 0x02, // load_palette 2
+        0x03, 0x02, // sprite_width 2
+        0x04, 0x01, // sprite_height 1
+        0xa2, 0x28, // i := sprite
+        0x60, 0x0a, // v0 := 10
+        0xd0, 0x01, // draw v0, v0
+        0x09, 0x02, // collision_colour 2
+        0xd0, 0x01, // draw v0, v0
+        0x00, 0xe0, // clear
+    ];
+    let mut data = PALETTE.to_vec();
+    data.extend([0x01, 0x02]);
+    let mut machine = megachip(&program, &data);
+
+    run(&mut machine, 8);
+    let fb = framebuffer(&machine);
+    assert_eq!((fb.colour(10, 10), fb.colour(11, 10)), (RED, GREEN));
+    assert_eq!((fb.index(10, 10), fb.index(11, 10)), (1, 2));
+    // Not shown until the next clear
+    assert_eq!(fb.shown(10, 10), BLACK);
+    assert_eq!(machine.registers[0xf], 0);
+
+    run(&mut machine, 2);
+    assert_eq!(machine.registers[0xf], 1);
+
+    run(&mut machine, 1);
+    let fb = framebuffer(&machine);
+    assert_eq!((fb.shown(10, 10), fb.shown(11, 10)), (RED, GREEN));
+    assert_eq!(fb.colour(10, 10), BLACK);
+}
+
+#[test]
+fn blend() {
+    let program = [
+        0x00, 0x11, // megachip_on
+        0xa2, 0x20, // i := palette
+        0x02, 0x02, // load_palette 2
+        0x03, 0x01, // sprite_width 1
+        0x04, 0x01, // sprite_height 1
+        0xa2, 0x28, // i := red pixel
+        0xd0, 0x01, // draw v0, v0
+        0x08, 0x02, // blend 50%
+        0xa2, 0x29, // i := green pixel
+        0xd0, 0x01, // draw v0, v0
+        0x08, 0x04, // blend add
+        0xd0, 0x01, // draw v0, v0
+    ];
+    let mut data = PALETTE.to_vec();
+    data.extend([0x01, 0x02]);
+    let mut machine = megachip(&program, &data);
+
+    run(&mut machine, 10);
+    assert_eq!(framebuffer(&machine).colour(0, 0), [0x7f, 0x7f, 0x00]);
+    run(&mut machine, 2);
+    assert_eq!(framebuffer(&machine).colour(0, 0), [0x7f, 0xff, 0x00]);
+}
+
+#[test]
+fn sample() {
+    let program = [
+        0xa2, 0x20, // i := sound
+        0x06, 0x01, // play_sample once
+        0x07, 0x00, // stop_sample
+    ];
+    let sound = [
+        0x1f, 0x40, // 8000 Hz
+        0x00, 0x00, 0x04, // 4 samples
+        0x00,
+        0x80, 0xff, 0x00, 0x80,
+    ];
+    let mut machine = megachip(&program, &sound);
+    run(&mut machine, 2);
+    let sample = machine.sample.as_ref().unwrap();
+    assert_eq!((sample.rate, sample.looping), (8000, false));
+    assert_eq!(&*sample.data, [0x80, 0xff, 0x00, 0x80]);
+    run(&mut machine, 1);
+    assert!(machine.sample.is_none());
+}
+
+#[test]
+fn sample_without_rate() {
+    let program = [
+        0xa2, 0x20, // i := sound
+        0x06, 0x00, // play_sample loop
+    ];
+    let sound = [0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x80];
+    let mut machine = megachip(&program, &sound);
+    run(&mut machine, 1);
+    let Err(crash) = machine.step() else {
+        panic!("a sound without a sample rate should not play");
+    };
+    assert!(matches!(
+        crash.error,
+        TickError::SampleError(SampleError::ZeroRate(0x220))
+    ));
+    assert!(machine.sample.is_none());
+}
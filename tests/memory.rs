@@ -0,0 +1,277 @@
+//! Accesses of instructions past the end of memory and into protected regions
+
+mod common;
+
+use chip_8::machine::{
+    Access, Machine, MemoryError, MemoryPolicy, Overflow, Platform, Region, TickError,
+};
+
+/// Run `opcode` with I = 0xFFE under `overflow`, registers and memory
+/// holding distinct values
+fn run_at_end(opcode: [u8; 2], overflow: Overflow) -> (Machine, Result<(), TickError>) {
+    let program = [0xaf, 0xfe, opcode[0], opcode[1]]; // i := 0xffe
+    let policy = MemoryPolicy {
+        overflow,
+        ..MemoryPolicy::default()
+    };
+    let mut machine = common::machine(Platform::Chip8, &program, |machine| {
+        machine.memory.policy = policy
+    });
+    machine.registers = [0x12, 0x34, 0x56, 0x78, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    machine
+        .memory
+        .range_mut(0xffe..0x1000)
+        .unwrap()
+        .copy_from_slice(&[0x81, 0x81]);
+    machine.step().unwrap();
+    let result = match machine.step() {
+        Ok(_) => Ok(()),
+        Err(crash) => Err(crash.error),
+    };
+    (machine, result)
+}
+
+fn memory_error(result: Result<(), TickError>) -> MemoryError {
+    match result {
+        Err(TickError::MemoryError(error)) => error,
+        result => panic!("expected a memory error, got {result:?}"),
+    }
+}
+
+#[test]
+fn store_registers_past_the_end() {
+    let save = [0xf3, 0x55]; // save v3
+
+    let (machine, result) = run_at_end(save, Overflow::Wrap);
+    result.unwrap();
+    assert_eq!(machine.memory.range(0xffe..0x1000).unwrap(), [0x12, 0x34]);
+    assert_eq!(machine.memory.range(0..2).unwrap(), [0x56, 0x78]);
+
+    let (machine, result) = run_at_end(save, Overflow::Error);
+    assert!(
+        matches!(memory_error(result), MemoryError::RangeOutOfBound(range) if range == (0xffe..0x1002))
+    );
+    assert_eq!(machine.memory.range(0xffe..0x1000).unwrap(), [0x81, 0x81]);
+    assert_eq!(machine.memory.range(0..2).unwrap(), [0xf0, 0x90]);
+
+    let (_, result) = run_at_end(save, Overflow::ErrorWithContext);
+    assert!(matches!(
+        memory_error(result),
+        MemoryError::AccessOutOfBound {
+            access: Access::Write,
+            addr: 0xffe,
+            len: 4,
+            end: 0x1000
+        }
+    ));
+}
+
+#[test]
+fn binary_coded_past_the_end() {
+    let bcd = [0xf0, 0x33]; // bcd v0, 18 = 0 1 8
+
+    let (machine, result) = run_at_end(bcd, Overflow::Wrap);
+    result.unwrap();
+    assert_eq!(machine.memory.range(0xffe..0x1000).unwrap(), [0, 1]);
+    assert_eq!(machine.memory.get(0).unwrap(), 8);
+
+    let (machine, result) = run_at_end(bcd, Overflow::Error);
+    assert!(
+        matches!(memory_error(result), MemoryError::RangeOutOfBound(range) if range == (0xffe..0x1001))
+    );
+    assert_eq!(machine.memory.range(0xffe..0x1000).unwrap(), [0x81, 0x81]);
+    assert_eq!(machine.memory.get(0).unwrap(), 0xf0);
+
+    let (_, result) = run_at_end(bcd, Overflow::ErrorWithContext);
+    assert!(matches!(
+        memory_error(result),
+        MemoryError::AccessOutOfBound {
+            access: Access::Write,
+            addr: 0xffe,
+            len: 3,
+            end: 0x1000
+        }
+    ));
+}
+
+#[test]
+fn sprite_past_the_end() {
+    let sprite = [0xd4, 0x43]; // sprite v4 v4 3
+
+    // Two rows from the end of memory, then the top of the 0 glyph
+    let (machine, result) = run_at_end(sprite, Overflow::Wrap);
+    result.unwrap();
+    let row = |y: usize| {
+        (0..8)
+            .map(|x| machine.screen.pixel(x, y))
+            .collect::<Vec<_>>()
+    };
+    let bits = |byte: u8| (0..8).map(|x| byte & (0x80 >> x) != 0).collect::<Vec<_>>();
+    assert_eq!(row(0), bits(0x81));
+    assert_eq!(row(1), bits(0x81));
+    assert_eq!(row(2), bits(0xf0));
+
+    let (machine, result) = run_at_end(sprite, Overflow::Error);
+    assert!(
+        matches!(memory_error(result), MemoryError::RangeOutOfBound(range) if range == (0xffe..0x1001))
+    );
+    assert!(machine.screen.pixels().iter().all(|lit| !lit));
+
+    let (_, result) = run_at_end(sprite, Overflow::ErrorWithContext);
+    assert!(matches!(
+        memory_error(result),
+        MemoryError::AccessOutOfBound {
+            access: Access::Read,
+            addr: 0xffe,
+            len: 3,
+            end: 0x1000
+        }
+    ));
+}
+
+#[test]
+fn fetch_at_the_last_byte() {
+    let policy = MemoryPolicy {
+        overflow: Overflow::Wrap,
+        ..MemoryPolicy::default()
+    };
+    let mut machine = common::machine(Platform::Chip8, &[0x1f, 0xff], |machine| {
+        machine.memory.policy = policy
+    }); // jump 0xfff
+        // v0 := 0xf0, ending with the first byte of the font
+    *machine.memory.get_mut(0xfff).unwrap() = 0x60;
+    machine.step().unwrap();
+    assert_eq!(machine.ip_register, 0xfff);
+    machine.step().unwrap();
+    assert_eq!(machine.register(0), 0xf0);
+    assert_eq!(machine.ip_register, 0x001);
+
+    let mut machine = Machine::new();
+    machine.ip_register = 0xfff;
+    let Err(crash) = machine.step() else {
+        panic!("the access should fail");
+    };
+    assert!(
+        matches!(
+            crash.error,
+            TickError::MemoryError(MemoryError::RangeOutOfBound(_))
+        ),
+        "{crash}"
+    );
+}
+
+#[test]
+fn protected_font() {
+    let program = [
+        0xa1, 0xfe, // i := 0x1fe
+        0xf3, 0x55, // save v3
+    ];
+    let policy = MemoryPolicy {
+        protect_font: true,
+        ..MemoryPolicy::default()
+    };
+    let mut machine = common::machine(Platform::Chip8, &program, |machine| {
+        machine.memory.policy = policy
+    });
+    machine.registers[..4].copy_from_slice(&[1, 2, 3, 4]);
+    machine.step().unwrap();
+    let Err(crash) = machine.step() else {
+        panic!("the access should fail");
+    };
+    assert!(matches!(
+        crash.error,
+        TickError::MemoryError(MemoryError::Protected {
+            addr: 0x1fe,
+            region: Region::Font
+        })
+    ));
+    // Not even the bytes of the program after the font
+    assert_eq!(
+        machine.memory.range(0x1fe..0x204).unwrap(),
+        [0, 0, 0xa1, 0xfe, 0xf3, 0x55]
+    );
+}
+
+#[test]
+fn protected_program() {
+    let program = [
+        0x60, 0xff, // v0 := 255
+        0xa2, 0x05, // i := 0x205, the last byte of the program
+        0xf0, 0x33, // bcd v0
+    ];
+    let policy = MemoryPolicy {
+        protect_program: true,
+        ..MemoryPolicy::default()
+    };
+    let mut machine = common::machine(Platform::Chip8, &program, |machine| {
+        machine.memory.policy = policy
+    });
+    machine.step().unwrap();
+    machine.step().unwrap();
+    let Err(crash) = machine.step() else {
+        panic!("the access should fail");
+    };
+    assert!(matches!(
+        crash.error,
+        TickError::MemoryError(MemoryError::Protected {
+            addr: 0x205,
+            region: Region::Program
+        })
+    ));
+    assert_eq!(
+        machine.memory.range(0x204..0x208).unwrap(),
+        [0xf0, 0x33, 0, 0]
+    );
+
+    // Past the program is free
+    machine.i_register = 0x206;
+    machine.step().unwrap();
+    assert_eq!(machine.memory.range(0x206..0x209).unwrap(), [2, 5, 5]);
+}
+
+/// Blocks compiled on both sides of the end of memory are dropped by a write
+/// wrapping around it
+#[cfg(feature = "jit")]
+#[test]
+fn jit_wrapped_write() {
+    use chip_8::machine::Jit;
+
+    let program = [
+        0x20, 0x00, // call 0x000, v2 := 1
+        0x2f, 0xfc, // call 0xffc, v3 := 1
+        0xaf, 0xfe, // i := 0xffe
+        0x60, 0x12, // v0 := 0x12
+        0x61, 0x16, // v1 := 0x16, jump 0x216
+        0x62, 0x62, // v2 := 0x62
+        0x63, 0x05, // v3 := 0x05, v2 := 5
+        0xf3, 0x55, // save v3, over 0xffe-0x001
+        0x20, 0x00, // call 0x000, now v2 := 5
+        0x2f, 0xfc, // call 0xffc, now v3 := 1 then jump 0x216
+        0x64, 0xaa, // v4 := 0xaa, skipped
+        0x65, 0xbb, // v5 := 0xbb
+        0x12, 0x18, // jump 0x218
+    ];
+    let policy = MemoryPolicy {
+        overflow: Overflow::Wrap,
+        ..MemoryPolicy::default()
+    };
+    let mut machine = common::machine(Platform::Chip8, &program, |machine| {
+        machine.memory.policy = policy
+    });
+    let memory = &mut machine.memory;
+    memory
+        .range_mut(0x000..0x004)
+        .unwrap()
+        .copy_from_slice(&[0x62, 0x01, 0x00, 0xee]);
+    memory
+        .range_mut(0xffc..0x1000)
+        .unwrap()
+        .copy_from_slice(&[0x63, 0x01, 0x00, 0xee]);
+    machine.speed = 40;
+
+    let mut jit = Jit::new();
+    jit.run(&mut machine).unwrap();
+    assert_eq!(machine.registers[2..6], [5, 1, 0, 0xbb]);
+    assert_eq!(machine.ip_register, 0x218);
+    assert_eq!(machine.call_stack.depth(), 1);
+}
@@ -0,0 +1,177 @@
+//! Screen size, load address and instructions of the hires CHIP-8 and the
+//! CHIP-8X, and detection of the platform of programs
+
+mod common;
+
+use chip_8::machine::{ColourBoard, Platform};
+
+#[test]
+fn hires() {
+    let mut program = vec![0x12, 0x60]; // jump 0x260, over the interpreter patch
+    program.resize(0x60, 0);
+    program.extend([
+        0x02, 0x30, // hires clear
+        0x12, 0xc0, // jump 0x2c0
+    ]);
+    program.resize(0xc0, 0);
+    program.extend([
+        0x60, 0x3c, // v0 := 60
+        0xd0, 0x05, // sprite v0 v0 5, the 0 glyph at I = 0
+    ]);
+    assert_eq!(Platform::detect(&program), Platform::HiRes);
+
+    let mut machine = common::machine(Platform::HiRes, &program, |_| {});
+    assert_eq!((machine.screen.width(), machine.screen.height()), (64, 64));
+    assert_eq!(machine.ip_register, 0x2c0);
+    machine.step().unwrap();
+    machine.step().unwrap();
+    // Below the 32 rows of the CHIP-8 screen
+    assert!(machine.screen.pixel(60, 60));
+}
+
+#[test]
+fn hires_jump_alone() {
+    // Jumps over its sprite data, without the hires patch nor any 0230
+    let mut program = vec![0x12, 0x60]; // jump 0x260
+    program.resize(0x60, 0xff);
+    program.extend([
+        0xa2, 0x02, // i := 0x202
+        0xd0, 0x05, // sprite v0 v0 5
+        0x12, 0x64, // jump 0x264
+    ]);
+    let platform = Platform::detect(&program);
+    assert_eq!(platform, Platform::Chip8);
+
+    let mut machine = common::machine(platform, &program, |_| {});
+    for _ in 0..3 {
+        machine.step().unwrap();
+    }
+    assert_eq!(machine.ip_register, 0x264);
+    assert!(machine.screen.pixel(0, 0));
+}
+
+#[test]
+fn chip_8x_load_address() {
+    let machine = common::machine(Platform::Chip8X, &[0x00, 0xe0], |_| {});
+    assert_eq!(machine.ip_register, 0x300);
+    assert_eq!(machine.memory.program(), 0x300..0x302);
+}
+
+#[test]
+fn chip_8x_colours() {
+    let program = [
+        0x60, 0x21, // v0 := 0x21, 3 zones from column 1
+        0x62, 0x10, // v2 := 0x10, 2 zones from row 0
+        0x61, 0x04, // v1 := 4, green
+        0xb0, 0x20, // zone colour v0 v2, to v1
+        0x60, 0x00, // v0 := 0
+        0x62, 0x02, // v2 := 2
+        0x61, 0x02, // v1 := 2, blue
+        0xb0, 0x23, // line colour v0 v2 3, to v1
+        0x02, 0xa0, // cycle background
+    ];
+    let mut machine = common::machine(Platform::Chip8X, &program, |_| {});
+    for _ in 0..4 {
+        machine.step().unwrap();
+    }
+    let board = machine.screen.colour_board().unwrap();
+    for row in 0..8 {
+        assert_eq!(board.cell(0, row), 1, "row {row}");
+        for column in 1..4 {
+            assert_eq!(board.cell(column, row), 4, "column {column}, row {row}");
+        }
+        assert_eq!(board.cell(4, row), 1, "row {row}");
+    }
+    assert_eq!(board.cell(1, 8), 1);
+
+    for _ in 0..4 {
+        machine.step().unwrap();
+    }
+    let board = machine.screen.colour_board().unwrap();
+    let column: Vec<_> = (0..6).map(|row| board.cell(0, row)).collect();
+    assert_eq!(column, [1, 1, 2, 2, 2, 1]);
+
+    assert_eq!(board.background(), ColourBoard::BACKGROUNDS[0]);
+    machine.step().unwrap();
+    let board = machine.screen.colour_board().unwrap();
+    assert_eq!(board.background(), ColourBoard::BACKGROUNDS[1]);
+}
+
+#[test]
+fn chip_8x_second_keypad() {
+    let program = [
+        0x60, 0x05, // v0 := 5
+        0xe0, 0xf2, // if key 5 is pressed on the second keypad, skip
+        0x00, 0xe0, // clear
+        0xe0, 0xf5, // if key 5 is not pressed on the second keypad, skip
+    ];
+    let mut machine = common::machine(Platform::Chip8X, &program, |_| {});
+    machine.keypad.press(5);
+    machine.step().unwrap();
+    machine.step().unwrap();
+    assert_eq!(machine.ip_register, 0x304);
+
+    machine.keypad_2.press(5);
+    machine.ip_register = 0x302;
+    machine.step().unwrap();
+    assert_eq!(machine.ip_register, 0x306);
+    machine.step().unwrap();
+    assert_eq!(machine.ip_register, 0x308);
+}
+
+#[test]
+fn chip_8x_instructions_on_other_platforms() {
+    let mut machine = common::machine(Platform::Chip8, &[0x02, 0xa0], |_| {});
+    assert!(machine.step().is_err());
+}
+
+#[test]
+fn detect() {
+    assert_eq!(Platform::detect(&[0x00, 0xe0, 0x12, 0x02]), Platform::Chip8);
+    // hires, the SUPER-CHIP 128x64 mode
+    assert_eq!(
+        Platform::detect(&[0x00, 0xff, 0x12, 0x02]),
+        Platform::SuperChip
+    );
+    // i := long 0x0000
+    assert_eq!(
+        Platform::detect(&[0xf0, 0x00, 0x00, 0x00]),
+        Platform::XoChip
+    );
+    // megachip on
+    assert_eq!(Platform::detect(&[0x00, 0x11]), Platform::MegaChip);
+
+    // Sprite data looking like a 16x16 sprite, after a jump over it
+    let program = [
+        0x12, 0x04, // jump 0x204
+        0xd0, 0x10, // data
+        0x00, 0xe0, // clear
+    ];
+    assert_eq!(Platform::detect(&program), Platform::Chip8);
+
+    // Calls return to the next instruction, and both sides of skips are followed
+    let program = [
+        0x22, 0x06, // call 0x206
+        0x30, 0x00, // if v0 != 0 then
+        0x00, 0xfb, // scroll right
+        0x00, 0xee, // return
+    ];
+    assert_eq!(Platform::detect(&program), Platform::SuperChip);
+}
+
+#[test]
+fn detected_large_program() {
+    // Longer than the 3.5 KiB CHIP-8 programs have room for
+    let mut program = vec![
+        0x60, 0x42, // v0 := 0x42
+    ];
+    program.resize(4000, 0);
+    program[3999] = 0x99;
+    let platform = Platform::detect(&program);
+    assert_eq!(platform, Platform::XoChip);
+
+    let mut machine = common::machine(platform, &program, |_| {});
+    assert_eq!(machine.memory.get(0x200 + 3999).unwrap(), 0x99);
+    machine.step().unwrap();
+    assert_eq!(machine.register(0), 0x42);
+}
@@ -0,0 +1,98 @@
+//! Symbol files, and the addresses and disassembly named after them
+
+use std::path::PathBuf;
+
+use chip_8::machine::instruction::dissassemble;
+use chip_8::machine::{Platform, Symbols};
+
+const SYMBOLS: &str = r#"
+labels = { main = 0x200, main_loop = 0x204 }
+
+[[lines]]
+address = 0x204
+file = "game.8o"
+line = 12
+source = "score += 1"
+"#;
+
+/// Path of `name` in the temporary directory, holding `text`
+fn write(name: &str, text: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(name);
+    std::fs::write(&path, text).unwrap();
+    path
+}
+
+/// [SYMBOLS], loaded from a file of its own for each test
+fn symbols(test: &str) -> Symbols {
+    Symbols::load(&write(&format!("chip-8-{test}.sym.toml"), SYMBOLS)).unwrap()
+}
+
+#[test]
+fn locate() {
+    let symbols = symbols("locate");
+    assert_eq!(symbols.label(0x204), Some("main_loop"));
+    assert_eq!(symbols.label(0x206), None);
+    assert_eq!(symbols.locate(0x200), "main");
+    assert_eq!(symbols.locate(0x202), "main+2");
+    assert_eq!(symbols.locate(0x20a), "main_loop+6");
+    // Before the first label
+    assert_eq!(symbols.locate(0x1fe), "1FE");
+}
+
+#[test]
+fn line() {
+    let symbols = symbols("line");
+    let line = symbols.line(0x204).unwrap();
+    assert_eq!(line.source, "score += 1");
+    assert_eq!(line.to_string(), "game.8o:12");
+    assert!(symbols.line(0x200).is_none());
+}
+
+#[test]
+fn load() {
+    // Lines are optional
+    let path = write("chip-8-labels.sym.toml", "[labels]\nmain = 0x200\n");
+    let symbols = Symbols::load(&path).unwrap();
+    assert_eq!(symbols.locate(0x200), "main");
+    assert!(symbols.line(0x200).is_none());
+
+    let path = write("chip-8-invalid.sym.toml", "labels = 3");
+    assert!(Symbols::load(&path).is_err());
+    assert!(Symbols::load(&std::env::temp_dir().join("chip-8-missing.sym.toml")).is_err());
+
+    // Found next to the ROM, or empty without a symbol file
+    write("chip-8-found.sym.toml", SYMBOLS);
+    let rom = std::env::temp_dir().join("chip-8-found.ch8");
+    assert_eq!(Symbols::find(&rom).unwrap().locate(0x204), "main_loop");
+    let rom = std::env::temp_dir().join("chip-8-no-symbols.ch8");
+    assert!(Symbols::find(&rom).unwrap().is_empty());
+}
+
+#[test]
+fn disassembly() {
+    let program = [
+        0x60, 0x01, // v0 := 1
+        0x12, 0x04, // jump 0x204
+        0x70, 0x01, // v0 += 1
+        0xff, 0xff, // data
+    ];
+    let plain = dissassemble(&program, 0x200, Platform::Chip8, &Symbols::default());
+    assert_eq!(
+        plain,
+        "200  6001  v0 := 1\n\
+         202  1204  jump 0x204\n\
+         204  7001  v0 += 1\n\
+         206  FFFF  data 0xffff\n"
+    );
+
+    let named = dissassemble(&program, 0x200, Platform::Chip8, &symbols("disassembly"));
+    assert_eq!(
+        named,
+        "main:\n\
+         200  6001  v0 := 1\n\
+         202  1204  jump 0x204\n\
+         main_loop:\n\
+         204  7001  score += 1              ; game.8o:12\n\
+         206  FFFF  data 0xffff\n"
+    );
+}